@@ -1,6 +1,7 @@
 /* basic STM32L412KBUx memory layout */
 MEMORY
 {
-  FLASH : ORIGIN = 0x08000000, LENGTH = 64K
+  /* the last 2K page is reserved for storing user data such as sequences */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 62K
   RAM   : ORIGIN = 0x20000000, LENGTH = 40K
 }
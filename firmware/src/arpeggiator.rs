@@ -0,0 +1,469 @@
+use heapless::Vec;
+
+use crate::rng::Rng;
+
+/// An arpeggiator which cycles through the held MIDI notes is represented here
+///
+/// The arpeggiator keeps its own list of held notes in the order they were played, and steps through them according
+/// to the selected order and octave range. It can run from an internal tempo or follow the incoming MIDI clock.
+pub struct Arpeggiator {
+    held_notes: Vec<u8, MAX_HELD_NOTES>,
+    order: ArpOrder,
+    num_octaves: u8,
+    gate_length: f32,
+
+    clock_source: ArpClockSource,
+    tick_rate_hz: f32,
+    tempo_bpm: f32,
+    internal_phase: f32,
+    midi_clock_running: bool,
+    midi_clocks_since_step: u32,
+    ticks_since_step: u32,
+    step_period_ticks: u32,
+
+    step_idx: usize,
+    ping_pong_idx: usize,
+    on_first_step: bool,
+    scrub_position: Option<f32>,
+    note: u8,
+    rng: Rng,
+}
+
+/// The orders in which the arpeggiator can play the held notes are represented here
+///
+/// * `Up` - lowest to highest
+///
+/// * `Down` - highest to lowest
+///
+/// * `UpDown` - lowest to highest and back down again, without repeating the top and bottom notes
+///
+/// * `Random` - a random held note at each step
+///
+/// * `AsPlayed` - in the order the notes were pressed
+#[derive(Clone, Copy)]
+pub enum ArpOrder {
+    Up,
+    Down,
+    UpDown,
+    Random,
+    AsPlayed,
+}
+
+/// The arpeggiator steps are clocked by either the internal tempo or the MIDI clock
+#[derive(Clone, Copy, PartialEq)]
+pub enum ArpClockSource {
+    Internal,
+    Midi,
+}
+
+/// While the arpeggiator runs the ribbon can either transpose the arpeggio, or scrub through its steps by position
+#[derive(Clone, Copy)]
+pub enum ArpRibbonMode {
+    Transpose,
+    Scrub,
+}
+
+impl Arpeggiator {
+    /// `Arpeggiator::new(r)` is a new arpeggiator with no held notes, which will be ticked at `r` hertz
+    pub fn new(tick_rate_hz: u32) -> Self {
+        Self {
+            held_notes: Vec::new(),
+            order: ArpOrder::Up,
+            num_octaves: 1,
+            gate_length: 0.5_f32,
+
+            clock_source: ArpClockSource::Internal,
+            tick_rate_hz: tick_rate_hz as f32,
+            tempo_bpm: 120.0_f32,
+            internal_phase: 0.0_f32,
+            // the clock is followed until a MIDI stop message, not all gear sends a start message
+            midi_clock_running: true,
+            midi_clocks_since_step: 0,
+            ticks_since_step: 0,
+            step_period_ticks: tick_rate_hz,
+
+            step_idx: 0,
+            ping_pong_idx: 0,
+            on_first_step: false,
+            scrub_position: None,
+            note: 0,
+            rng: Rng::new(0x1234_5678),
+        }
+    }
+
+    /// `arp.set_order(o)` sets the order that the held notes are played in to `o`
+    pub fn set_order(&mut self, order: ArpOrder) {
+        self.order = order;
+    }
+
+    /// `arp.set_num_octaves(n)` sets how many octaves the held notes are repeated over, clamped to `[1, MAX_OCTAVES]`
+    pub fn set_num_octaves(&mut self, num_octaves: u8) {
+        self.num_octaves = num_octaves.clamp(1, MAX_OCTAVES);
+    }
+
+    /// `arp.set_gate_length(g)` sets the gate length as a fraction of each step, clamped to `[0.0, 1.0]`
+    pub fn set_gate_length(&mut self, gate_length: f32) {
+        self.gate_length = gate_length.clamp(0.0_f32, 1.0_f32);
+    }
+
+    /// `arp.set_clock_source(c)` sets whether the steps follow the internal tempo or the MIDI clock
+    pub fn set_clock_source(&mut self, clock_source: ArpClockSource) {
+        self.clock_source = clock_source;
+    }
+
+    /// `arp.set_tempo_bpm(t)` sets the internal tempo to `t` beats per minute, each beat has `STEPS_PER_BEAT` steps
+    pub fn set_tempo_bpm(&mut self, tempo_bpm: f32) {
+        self.tempo_bpm = tempo_bpm.max(1.0_f32);
+    }
+
+    /// `arp.note_on(n)` adds the note `n` to the held notes, if there is room
+    pub fn note_on(&mut self, note: u8) {
+        if !self.held_notes.contains(&note) {
+            // if there is no room the note is simply ignored
+            let _ = self.held_notes.push(note);
+        }
+
+        // the first note of a new chord starts the pattern over, and the rest of the chord joins it if it arrives before
+        // the first step is over
+        if self.held_notes.len() == 1 || self.on_first_step {
+            self.restart();
+        }
+    }
+
+    /// `arp.note_off(n)` removes the note `n` from the held notes
+    pub fn note_off(&mut self, note: u8) {
+        self.held_notes.retain(|&n| n != note);
+    }
+
+    /// `arp.midi_clock()` advances the arpeggiator by one MIDI clock, there are 24 clocks per beat
+    pub fn midi_clock(&mut self) {
+        if self.clock_source != ArpClockSource::Midi || !self.midi_clock_running {
+            return;
+        }
+
+        self.midi_clocks_since_step += 1;
+        if MIDI_CLOCKS_PER_STEP <= self.midi_clocks_since_step {
+            self.midi_clocks_since_step = 0;
+            self.advance();
+        }
+    }
+
+    /// `arp.midi_start()` restarts the pattern and starts following the MIDI clock
+    pub fn midi_start(&mut self) {
+        self.restart();
+        self.midi_clock_running = true;
+    }
+
+    /// `arp.midi_continue()` resumes following the MIDI clock from where it stopped
+    pub fn midi_continue(&mut self) {
+        self.midi_clock_running = true;
+    }
+
+    /// `arp.midi_stop()` stops following the MIDI clock
+    pub fn midi_stop(&mut self) {
+        self.midi_clock_running = false;
+    }
+
+    /// `arp.scrub(p)` selects the step directly from the ribbon position `p` in `[0.0, 1.0]`, or `None` to run freely
+    ///
+    /// While scrubbing the clock is ignored and the gate stays open.
+    pub fn scrub(&mut self, position: Option<f32>) {
+        self.scrub_position = position;
+    }
+
+    /// `arp.tick()` advances the internal clock and the gate timing
+    ///
+    /// It is required to call this function at the tick rate given when the arpeggiator was created.
+    pub fn tick(&mut self) {
+        self.ticks_since_step = self.ticks_since_step.saturating_add(1);
+
+        if let Some(position) = self.scrub_position {
+            let len = self.pattern_len();
+            if 0 < len {
+                let idx = ((position * len as f32) as usize).min(len - 1);
+                self.note = self.pattern_note(idx);
+            }
+            return;
+        }
+
+        if self.clock_source == ArpClockSource::Internal {
+            let steps_per_tick =
+                self.tempo_bpm * STEPS_PER_BEAT as f32 / (60.0_f32 * self.tick_rate_hz);
+            self.internal_phase += steps_per_tick;
+            if 1.0_f32 <= self.internal_phase {
+                self.internal_phase -= 1.0_f32;
+                self.advance();
+            }
+        }
+    }
+
+    /// `arp.is_active()` is true iff any notes are held, so the arpeggiator should be heard
+    pub fn is_active(&self) -> bool {
+        !self.held_notes.is_empty()
+    }
+
+    /// `arp.note()` is the current arpeggiated MIDI note number
+    pub fn note(&self) -> u8 {
+        self.note
+    }
+
+    /// `arp.gate()` is the arpeggiator gate, which is high for the gate length fraction of each step
+    pub fn gate(&self) -> bool {
+        if !self.is_active() {
+            return false;
+        }
+        if self.scrub_position.is_some() {
+            return true;
+        }
+        (self.ticks_since_step as f32) < self.gate_length * self.step_period_ticks as f32
+    }
+
+    /// `arp.restart()` starts the pattern over from the first step
+    fn restart(&mut self) {
+        self.step_idx = 0;
+        self.ping_pong_idx = 0;
+        self.internal_phase = 0.0_f32;
+        self.midi_clocks_since_step = 0;
+        self.advance_to(0);
+        self.on_first_step = true;
+    }
+
+    /// `arp.advance()` moves to the next step of the pattern
+    fn advance(&mut self) {
+        let len = self.pattern_len();
+        if len == 0 {
+            return;
+        }
+
+        let idx = match self.order {
+            ArpOrder::Random => self.rng.next_below(len),
+            ArpOrder::UpDown if 1 < len => {
+                // the up and down pattern visits the ends once per cycle
+                let cycle_len = 2 * len - 2;
+                self.ping_pong_idx = (self.ping_pong_idx + 1) % cycle_len;
+                if self.ping_pong_idx < len {
+                    self.ping_pong_idx
+                } else {
+                    cycle_len - self.ping_pong_idx
+                }
+            }
+            _ => (self.step_idx + 1) % len,
+        };
+
+        // the step period is measured so that the gate length works with either clock source
+        self.step_period_ticks = self.ticks_since_step.max(1);
+        self.advance_to(idx);
+        self.on_first_step = false;
+    }
+
+    /// `arp.advance_to(i)` moves to step `i` of the pattern and opens the gate
+    fn advance_to(&mut self, idx: usize) {
+        self.step_idx = idx;
+        self.ticks_since_step = 0;
+        if idx < self.pattern_len() {
+            self.note = self.pattern_note(idx);
+        }
+    }
+
+    /// `arp.pattern_len()` is the number of steps in the pattern, the held notes repeated over the octave range
+    fn pattern_len(&self) -> usize {
+        self.held_notes.len() * self.num_octaves as usize
+    }
+
+    /// `arp.pattern_note(i)` is the MIDI note at step `i` of the pattern, `i` must be less than the pattern length
+    fn pattern_note(&self, idx: usize) -> u8 {
+        let num_notes = self.held_notes.len();
+        let octave = (idx / num_notes) as u8;
+        let note_idx = idx % num_notes;
+
+        let (note, octave) = match self.order {
+            ArpOrder::AsPlayed => (self.held_notes[note_idx], octave),
+            // the down pattern is the up pattern backwards, starting from the top octave
+            ArpOrder::Down => (
+                nth_lowest(&self.held_notes, num_notes - 1 - note_idx),
+                self.num_octaves - 1 - octave,
+            ),
+            _ => (nth_lowest(&self.held_notes, note_idx), octave),
+        };
+
+        note.saturating_add(12 * octave).min(MAX_NOTE)
+    }
+}
+
+/// `nth_lowest(ns, i)` is the `i`th lowest note in the notes `ns`, where `0` is the lowest
+fn nth_lowest(notes: &[u8], idx: usize) -> u8 {
+    let mut sorted: Vec<u8, MAX_HELD_NOTES> = Vec::new();
+    for &note in notes {
+        // there is room for every note since both lists have the same capacity
+        let pos = sorted
+            .iter()
+            .position(|&n| note < n)
+            .unwrap_or(sorted.len());
+        let _ = sorted.insert(pos, note);
+    }
+    sorted[idx]
+}
+
+/// The maximum number of notes that the arpeggiator can hold at once
+const MAX_HELD_NOTES: usize = 16;
+
+/// The maximum number of octaves that the held notes can be repeated over
+pub const MAX_OCTAVES: u8 = 4;
+
+/// The highest valid MIDI note number
+const MAX_NOTE: u8 = 127;
+
+/// The number of arpeggiator steps per beat, the steps are sixteenth notes
+const STEPS_PER_BEAT: u32 = 4;
+
+/// The MIDI clock has 24 pulses per beat
+const MIDI_CLOCKS_PER_STEP: u32 = 24 / STEPS_PER_BEAT;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `arp_holding(o, ns)` is a MIDI clocked arpeggiator in order `o` holding the notes `ns`, pressed in that order
+    fn arp_holding(order: ArpOrder, notes: &[u8]) -> Arpeggiator {
+        let mut arp = Arpeggiator::new(1_000);
+        arp.set_order(order);
+        arp.set_clock_source(ArpClockSource::Midi);
+        for &note in notes.iter() {
+            arp.note_on(note);
+        }
+        arp
+    }
+
+    /// `step(arp)` advances the arpeggiator `arp` by one step of MIDI clocks
+    fn step(arp: &mut Arpeggiator) {
+        for _ in 0..MIDI_CLOCKS_PER_STEP {
+            arp.midi_clock();
+        }
+    }
+
+    /// `play(arp)` is the note at each of the next 8 steps of the arpeggiator `arp`, starting with the current one
+    fn play(arp: &mut Arpeggiator) -> [u8; 8] {
+        let mut notes = [0; 8];
+        for note in notes.iter_mut() {
+            *note = arp.note();
+            step(arp);
+        }
+        notes
+    }
+
+    #[test]
+    fn up_and_down_orders() {
+        let mut arp = arp_holding(ArpOrder::Up, &[64, 60, 67]);
+        assert_eq!(play(&mut arp), [60, 64, 67, 60, 64, 67, 60, 64]);
+
+        let mut arp = arp_holding(ArpOrder::Down, &[64, 60, 67]);
+        assert_eq!(play(&mut arp), [67, 64, 60, 67, 64, 60, 67, 64]);
+    }
+
+    #[test]
+    fn up_down_doesnt_repeat_the_ends() {
+        let mut arp = arp_holding(ArpOrder::UpDown, &[64, 60, 67]);
+        assert_eq!(play(&mut arp), [60, 64, 67, 64, 60, 64, 67, 64]);
+
+        // a single note has nowhere to turn around
+        let mut arp = arp_holding(ArpOrder::UpDown, &[60]);
+        assert_eq!(play(&mut arp), [60; 8]);
+    }
+
+    #[test]
+    fn as_played_order() {
+        let mut arp = arp_holding(ArpOrder::AsPlayed, &[64, 60, 67]);
+        assert_eq!(play(&mut arp), [64, 60, 67, 64, 60, 67, 64, 60]);
+    }
+
+    #[test]
+    fn octave_span() {
+        let mut arp = arp_holding(ArpOrder::Up, &[]);
+        arp.set_num_octaves(3);
+        arp.note_on(60);
+        arp.note_on(64);
+        assert_eq!(play(&mut arp), [60, 64, 72, 76, 84, 88, 60, 64]);
+
+        let mut arp = arp_holding(ArpOrder::Down, &[]);
+        arp.set_num_octaves(2);
+        arp.note_on(60);
+        arp.note_on(64);
+        assert_eq!(play(&mut arp), [76, 72, 64, 60, 76, 72, 64, 60]);
+
+        // the span is clamped, and its top is kept within the MIDI note range
+        let mut arp = arp_holding(ArpOrder::Up, &[]);
+        arp.set_num_octaves(MAX_OCTAVES + 1);
+        arp.note_on(120);
+        assert_eq!(play(&mut arp)[..5], [120, 127, 127, 127, 120]);
+    }
+
+    #[test]
+    fn notes_added_mid_pattern_join_it() {
+        let mut arp = arp_holding(ArpOrder::Up, &[60, 67]);
+        step(&mut arp);
+        assert_eq!(arp.note(), 67);
+        arp.note_on(64);
+        step(&mut arp);
+        assert_eq!(play(&mut arp)[..6], [67, 60, 64, 67, 60, 64]);
+    }
+
+    #[test]
+    fn notes_removed_mid_pattern_leave_it() {
+        let mut arp = arp_holding(ArpOrder::Up, &[60, 64, 67, 72]);
+        step(&mut arp);
+        step(&mut arp);
+        step(&mut arp);
+        arp.note_off(64);
+        arp.note_off(72);
+        step(&mut arp);
+        assert_eq!(play(&mut arp)[..4], [60, 67, 60, 67]);
+
+        arp.note_off(60);
+        arp.note_off(67);
+        assert!(!arp.is_active());
+        assert!(!arp.gate());
+    }
+
+    #[test]
+    fn midi_clock_division() {
+        let mut arp = arp_holding(ArpOrder::Up, &[60, 64]);
+        // a step is a sixteenth note, six of the 24 clocks per beat
+        for _ in 0..5 {
+            arp.midi_clock();
+        }
+        assert_eq!(arp.note(), 60);
+        arp.midi_clock();
+        assert_eq!(arp.note(), 64);
+
+        // the clock is ignored while stopped
+        arp.midi_stop();
+        step(&mut arp);
+        assert_eq!(arp.note(), 64);
+        arp.midi_continue();
+        step(&mut arp);
+        assert_eq!(arp.note(), 60);
+    }
+
+    #[test]
+    fn internal_tempo_and_gate_length() {
+        // 120 BPM is 8 sixteenth notes a second, exactly 128 ticks each at 1024 hertz
+        let mut arp = Arpeggiator::new(1_024);
+        arp.note_on(60);
+        arp.note_on(64);
+        arp.set_gate_length(0.25_f32);
+        for _ in 0..127 {
+            arp.tick();
+        }
+        assert_eq!(arp.note(), 60);
+        arp.tick();
+        assert_eq!(arp.note(), 64);
+
+        // the gate is open for a quarter of each measured step
+        let gates: [bool; 128] = core::array::from_fn(|_| {
+            arp.tick();
+            arp.gate()
+        });
+        assert_eq!(gates.iter().filter(|&&g| g).count(), 32);
+        assert!(gates[..31].iter().all(|&g| g));
+    }
+}
@@ -1,10 +1,12 @@
+use cortex_m::peripheral::DWT;
+use heapless::Deque;
 use stm32l4xx_hal::{
     adc::{SampleTime, Sequence, ADC},
     delay::Delay,
     device::SPI1,
     gpio::{Alternate, Input, Output, Pin, PullUp, PushPull, H8, L8},
     hal::spi::{Mode, Phase, Polarity},
-    pac::{ADC1, DMA1, TIM15, TIM2, TIM6, USART1},
+    pac::{ADC1, DMA1, FLASH, TIM15, TIM2, TIM6, USART1},
     prelude::*,
     serial,
     spi::Spi,
@@ -26,6 +28,7 @@ pub struct Board {
     // USART for MIDI
     _midi_tx: serial::Tx<USART1>,
     midi_rx: serial::Rx<USART1>,
+    midi_tx_queue: Deque<u8, MIDI_TX_QUEUE_LEN>,
 
     // SPI for DAC
     spi: SpiBus,
@@ -52,7 +55,7 @@ impl Board {
         // general peripheral housekeeping, core peripherals and clocks
         //
         ////////////////////////////////////////////////////////////////////////
-        let cp = cortex_m::Peripherals::take().unwrap();
+        let mut cp = cortex_m::Peripherals::take().unwrap();
         let dp = stm32l4xx_hal::pac::Peripherals::take().unwrap();
         let mut flash = dp.FLASH.constrain();
         let mut rcc = dp.RCC.constrain();
@@ -72,6 +75,10 @@ impl Board {
 
         let mut delay = Delay::new(cp.SYST, clocks);
 
+        // the cycle counter is used to measure how long the processing takes
+        cp.DCB.enable_trace();
+        cp.DWT.enable_cycle_counter();
+
         ////////////////////////////////////////////////////////////////////////
         //
         // ADC
@@ -257,6 +264,7 @@ impl Board {
         Self {
             _midi_tx: tx,
             midi_rx: rx,
+            midi_tx_queue: Deque::new(),
             spi,
             nss,
             delay,
@@ -304,30 +312,51 @@ impl Board {
         }
     }
 
-    /// `board.serial_write_all(bs)` writes all bytes `bs` via the serial port
+    /// `board.serial_write_all(bs)` queues all bytes `bs` to be written via the serial port, without waiting
     ///
-    /// # Requires
+    /// The bytes are sent by DMA in the background. While a transfer is still going the bytes wait in the queue and
+    /// are sent by a later call, so this should be called regularly even when there are no new bytes.
     ///
-    /// * `bytes` is no greater than `MIDI_TX_BUFF_LEN` in length
-    pub fn _serial_write_all(&mut self, bytes: &[u8]) {
-        if bytes.is_empty() {
-            return;
+    /// # Returns:
+    ///
+    /// * true iff the bytes were queued, false if they were all dropped because the queue is full
+    pub fn serial_write_all(&mut self, bytes: &[u8]) -> bool {
+        // the bytes are queued all or nothing so that messages are never cut short
+        let queued = bytes.len() <= self.midi_tx_queue.capacity() - self.midi_tx_queue.len();
+        if queued {
+            for &byte in bytes.iter() {
+                self.midi_tx_queue.push_back(byte).ok();
+            }
         }
 
-        // use DMA to send the bytes
-        unsafe {
-            MIDI_USART_DMA_BUFF[..bytes.len()].copy_from_slice(bytes);
+        self.serial_start_transfer();
+        queued
+    }
 
-            while (*USART1::ptr()).isr.read().tc().bit_is_clear() {
-                // wait for any ongoing transfer to complete
+    /// `board.serial_start_transfer()` starts sending the queued bytes by DMA, unless the last transfer is still going
+    fn serial_start_transfer(&mut self) {
+        let transfer_complete = unsafe { (*USART1::ptr()).isr.read().tc().bit_is_set() };
+        if !transfer_complete || self.midi_tx_queue.is_empty() {
+            return;
+        }
+
+        // the buffer is only touched once the last transfer out of it is complete
+        let buff = unsafe { &mut *core::ptr::addr_of_mut!(MIDI_USART_DMA_BUFF) };
+        let mut len = 0;
+        for slot in buff.iter_mut() {
+            match self.midi_tx_queue.pop_front() {
+                Some(byte) => *slot = byte,
+                None => break,
             }
+            len += 1;
+        }
 
+        // use DMA to send the bytes
+        unsafe {
             // disable DMA
             (*DMA1::ptr()).ccr4.modify(|_, w| w.en().disabled());
             // set the length for the data transfer
-            (*DMA1::ptr())
-                .cndtr4
-                .write(|w| w.ndt().bits(bytes.len() as u16));
+            (*DMA1::ptr()).cndtr4.write(|w| w.ndt().bits(len as u16));
             // clear the transfer complete flag
             (*USART1::ptr()).icr.write(|w| w.tccf().set_bit());
             // enable DMA to start the transfer
@@ -335,6 +364,97 @@ impl Board {
         }
     }
 
+    /// `board.read_storage(o, ws)` fills the words `ws` with the words saved in the flash storage page, starting `o`
+    /// words into the page
+    ///
+    /// # Requires
+    ///
+    /// * `offset` plus the length of `words` is no greater than `STORAGE_NUM_WORDS`
+    pub fn read_storage(&self, offset: usize, words: &mut [u64]) {
+        for (i, word) in words.iter_mut().enumerate() {
+            let address = STORAGE_PAGE_ADDRESS + (offset + i) * 8;
+            *word = unsafe { core::ptr::read_volatile(address as *const u64) };
+        }
+    }
+
+    /// `board.write_storage(ws)` erases the flash storage page and saves the words `ws` to it
+    ///
+    /// The CPU stalls while the page is erased and programmed, which takes around 45 milliseconds. The code runs from the
+    /// same flash bank, so nothing else runs in that time and the outputs hold their last values.
+    ///
+    /// # Requires
+    ///
+    /// * `words` is no greater than `STORAGE_NUM_WORDS` in length
+    pub fn write_storage(&mut self, words: &[u64]) {
+        unsafe {
+            let flash = &*FLASH::ptr();
+
+            // unlock the flash control register
+            if flash.cr.read().lock().bit_is_set() {
+                flash.keyr.write(|w| w.keyr().bits(FLASH_KEY_1));
+                flash.keyr.write(|w| w.keyr().bits(FLASH_KEY_2));
+            }
+            wait_for_flash(flash);
+            // clear any leftover error flags, they are cleared by writing ones
+            flash.sr.write(|w| w.bits(FLASH_SR_ERROR_FLAGS));
+
+            // erase the page
+            flash.cr.modify(|_, w| {
+                w.per()
+                    .set_bit()
+                    .pnb()
+                    .bits(STORAGE_PAGE as u8)
+                    .start()
+                    .set_bit()
+            });
+            wait_for_flash(flash);
+            flash.cr.modify(|_, w| w.per().clear_bit());
+
+            // program the words, each one is written as two halves back to back
+            flash.cr.modify(|_, w| w.pg().set_bit());
+            for (i, &word) in words.iter().enumerate() {
+                let address = STORAGE_PAGE_ADDRESS + i * 8;
+                core::ptr::write_volatile(address as *mut u32, word as u32);
+                core::ptr::write_volatile((address + 4) as *mut u32, (word >> 32) as u32);
+                wait_for_flash(flash);
+            }
+            flash.cr.modify(|_, w| w.pg().clear_bit());
+
+            flash.cr.modify(|_, w| w.lock().set_bit());
+        }
+    }
+
+    /// `board.update_storage(ss)` saves each of the sections `ss` to the flash storage page, the rest of the page is
+    /// kept as it was
+    ///
+    /// Each section is an offset in words into the page and the words to save there. The page is erased and programmed
+    /// once for all of the sections, with the same stall as `board.write_storage`.
+    ///
+    /// # Requires
+    ///
+    /// * the offset plus the length of the words of each section is no greater than `STORAGE_NUM_WORDS`
+    ///
+    /// # Returns:
+    ///
+    /// * true iff the page reads back just as it was written
+    pub fn update_storage(&mut self, sections: &[(usize, &[u64])]) -> bool {
+        // the page is staged in a static buffer rather than taking 2K of the stack, borrowing the board mutably makes
+        // this the only use of it
+        let page = unsafe { &mut *core::ptr::addr_of_mut!(STORAGE_STAGING_BUFF) };
+
+        self.read_storage(0, page);
+        for &(offset, words) in sections.iter() {
+            page[offset..offset + words.len()].copy_from_slice(words);
+        }
+        self.write_storage(page);
+
+        page.iter().enumerate().all(|(i, &word)| {
+            let mut saved = [0];
+            self.read_storage(i, &mut saved);
+            saved[0] == word
+        })
+    }
+
     /// `board.serial_read()` is the optional byte read from the USART.
     pub fn serial_read(&mut self) -> Option<u8> {
         match self.midi_rx.read() {
@@ -360,6 +480,11 @@ impl Board {
         self.delay.delay_ms(ms);
     }
 
+    /// `board.cycle_count()` is the free running count of system clock cycles, it wraps around every ~54 seconds
+    pub fn cycle_count(&self) -> u32 {
+        DWT::cycle_count()
+    }
+
     /// board.get_tim2_timeout()` is true iff timer TIM2 has timed out, self clearing.
     pub fn get_tim2_timeout(&self) -> bool {
         unsafe {
@@ -430,6 +555,9 @@ pub const DAC8164_MAX_VOLTS: f32 = 10.0_f32;
 /// The baud rate required for MIDI communication
 pub const MIDI_BAUD_RATE_HZ: u32 = 31_250;
 
+/// The number of 64 bit words which fit in the flash storage page
+pub const STORAGE_NUM_WORDS: usize = FLASH_PAGE_SIZE / 8;
+
 ////////////////////////////////////////////////////////////////////////////////
 //
 // Private constants and static variables
@@ -443,6 +571,22 @@ const DAC8164_COUNTS_PER_VOLT: f32 = DAC8164_MAX_COUNT as f32 / DAC8164_MAX_VOLT
 const NUM_ADC_DMA_SIGNALS: usize = 5;
 static mut ADC_DMA_BUFF: [u16; NUM_ADC_DMA_SIGNALS] = [0; NUM_ADC_DMA_SIGNALS];
 
+/// The flash page used for storage, it is kept out of the program area in memory.x
+const STORAGE_PAGE: usize = 31;
+const FLASH_PAGE_SIZE: usize = 2_048;
+const STORAGE_PAGE_ADDRESS: usize = 0x0800_0000 + STORAGE_PAGE * FLASH_PAGE_SIZE;
+
+/// The keys which unlock the flash control register, in order
+const FLASH_KEY_1: u32 = 0x4567_0123;
+const FLASH_KEY_2: u32 = 0xCDEF_89AB;
+
+/// All of the error flags in the flash status register
+const FLASH_SR_ERROR_FLAGS: u32 = 0x0000_C3FA;
+
+static mut STORAGE_STAGING_BUFF: [u64; STORAGE_NUM_WORDS] = [0; STORAGE_NUM_WORDS];
+
+/// Bytes for the MIDI output wait in a queue until the DMA buffer is free, a few ticks worth of the busiest output
+const MIDI_TX_QUEUE_LEN: usize = 64;
 const MIDI_TX_BUFF_LEN: usize = 16;
 static mut MIDI_USART_DMA_BUFF: [u8; MIDI_TX_BUFF_LEN] = [0; MIDI_TX_BUFF_LEN];
 
@@ -461,6 +605,13 @@ fn adc_fs_to_normalized_fl(val: u16) -> f32 {
     (val as f32) / (ADC_MAX as f32)
 }
 
+/// `wait_for_flash(f)` busy-waits until the flash `f` has finished the current operation
+fn wait_for_flash(flash: &stm32l4xx_hal::pac::flash::RegisterBlock) {
+    while flash.sr.read().bsy().bit_is_set() {
+        // wait for the erase or program operation to complete
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 //
 // Public enums
@@ -478,7 +629,7 @@ pub enum AdcPin {
 }
 
 /// Channels of the onboard DAC are represented here
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum Dac8164Channel {
     A = 0b000,
     B = 0b010,
@@ -487,7 +638,7 @@ pub enum Dac8164Channel {
 }
 
 /// Valid states of a 3-way switch are represented here
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum Switch3wayState {
     Up,
     Middle,
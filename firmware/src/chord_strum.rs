@@ -0,0 +1,172 @@
+/// A chord strummer which turns ribbon zones into arpeggiated diatonic chords is represented here
+///
+/// The ribbon is divided into zones, one for each degree of the scale over a couple of octaves. While the finger is
+/// held in a zone the tones of that degree's diatonic chord are stepped through at the set tempo, each step is a new
+/// note so the gate can be retriggered.
+pub struct ChordStrum {
+    tick_rate_hz: f32,
+    root: u8,
+    scale: Scale,
+    chord_size: ChordSize,
+    tempo_bpm: f32,
+
+    phase: f32,
+    zone: Option<usize>,
+    tone_idx: usize,
+    note: u8,
+    stepped: bool,
+}
+
+/// The scales which the chords can be built from are represented here
+#[derive(Clone, Copy)]
+pub enum Scale {
+    Major,
+    NaturalMinor,
+}
+
+/// The chords can be triads or seventh chords
+#[derive(Clone, Copy)]
+pub enum ChordSize {
+    Triad,
+    Seventh,
+}
+
+impl ChordStrum {
+    /// `ChordStrum::new(r)` is a new chord strummer in C major, which will be ticked at `r` hertz
+    pub fn new(tick_rate_hz: u32) -> Self {
+        Self {
+            tick_rate_hz: tick_rate_hz as f32,
+            root: 0,
+            scale: Scale::Major,
+            chord_size: ChordSize::Triad,
+            tempo_bpm: 120.0_f32,
+
+            phase: 0.0_f32,
+            zone: None,
+            tone_idx: 0,
+            note: 0,
+            stepped: false,
+        }
+    }
+
+    /// `strum.set_root(r)` sets the key to `r` semitones above C, wrapped to one octave
+    pub fn set_root(&mut self, root: u8) {
+        self.root = root % 12;
+    }
+
+    /// `strum.set_scale(s)` sets the scale that the chords are built from to `s`
+    pub fn set_scale(&mut self, scale: Scale) {
+        self.scale = scale;
+    }
+
+    /// `strum.set_chord_size(c)` sets whether the chords are triads or seventh chords
+    pub fn set_chord_size(&mut self, chord_size: ChordSize) {
+        self.chord_size = chord_size;
+    }
+
+    /// `strum.set_tempo_bpm(t)` sets the strum rate to `t` beats per minute, the chord tones are sixteenth notes
+    pub fn set_tempo_bpm(&mut self, tempo_bpm: f32) {
+        self.tempo_bpm = tempo_bpm.max(1.0_f32);
+    }
+
+    /// `strum.tick(p)` advances the strum with the ribbon position `p` in `[0.0, 1.0]`, or `None` if the finger is up
+    ///
+    /// It is required to call this function at the tick rate given when the strummer was created.
+    pub fn tick(&mut self, position: Option<f32>) {
+        let zone = position.map(|p| {
+            let zone_pos = p * NUM_ZONES as f32;
+            match self.zone {
+                // stay in the current zone until the finger is clearly past the edge, so a finger resting right on
+                // the edge doesn't keep restarting the chord
+                Some(z)
+                    if z as f32 - ZONE_HYSTERESIS < zone_pos
+                        && zone_pos < (z + 1) as f32 + ZONE_HYSTERESIS =>
+                {
+                    z
+                }
+                _ => (zone_pos as usize).min(NUM_ZONES - 1),
+            }
+        });
+
+        match (zone, self.zone) {
+            (None, _) => (),
+            // a new touch or a new zone starts the chord over right away
+            (Some(z), prev) if prev != Some(z) => {
+                self.phase = 0.0_f32;
+                self.step_to(z, 0);
+            }
+            (Some(z), _) => {
+                let steps_per_tick =
+                    self.tempo_bpm * STEPS_PER_BEAT / (60.0_f32 * self.tick_rate_hz);
+                self.phase += steps_per_tick;
+                if 1.0_f32 <= self.phase {
+                    self.phase -= 1.0_f32;
+                    let num_tones = self.chord_size.num_tones();
+                    self.step_to(z, (self.tone_idx + 1) % num_tones);
+                }
+            }
+        }
+
+        self.zone = zone;
+    }
+
+    /// `strum.note()` is the current chord tone in semitones above 0 volts, held after the finger lifts
+    pub fn note(&self) -> u8 {
+        self.note
+    }
+
+    /// `strum.take_step()` is true iff a new chord tone has started since the last call, self clearing
+    pub fn take_step(&mut self) -> bool {
+        let stepped = self.stepped;
+        self.stepped = false;
+        stepped
+    }
+
+    /// `strum.step_to(z, t)` moves to tone `t` of the chord for zone `z`
+    fn step_to(&mut self, zone: usize, tone_idx: usize) {
+        self.tone_idx = tone_idx;
+
+        let degree = zone % SCALE_LEN;
+        let octave = zone / SCALE_LEN;
+
+        // chords are stacked in thirds, which is every other degree of the scale
+        let chord_degree = degree + 2 * tone_idx;
+        let chord_octave = octave + chord_degree / SCALE_LEN;
+        let interval = self.scale.intervals()[chord_degree % SCALE_LEN];
+
+        self.note = self.root + 12 * chord_octave as u8 + interval;
+        self.stepped = true;
+    }
+}
+
+impl Scale {
+    /// `scale.intervals()` is the semitones above the root for each degree of the scale
+    fn intervals(&self) -> [u8; SCALE_LEN] {
+        match self {
+            Scale::Major => [0, 2, 4, 5, 7, 9, 11],
+            Scale::NaturalMinor => [0, 2, 3, 5, 7, 8, 10],
+        }
+    }
+}
+
+impl ChordSize {
+    /// `size.num_tones()` is the number of tones in the chord
+    fn num_tones(&self) -> usize {
+        match self {
+            ChordSize::Triad => 3,
+            ChordSize::Seventh => 4,
+        }
+    }
+}
+
+/// The number of degrees in the diatonic scales
+const SCALE_LEN: usize = 7;
+
+/// The ribbon has a zone for each scale degree over two octaves
+const NUM_ZONES: usize = SCALE_LEN * 2;
+
+/// How far past the edge of a zone the finger must move to select the next zone, as a fraction of a zone
+const ZONE_HYSTERESIS: f32 = 0.2_f32;
+
+/// The number of chord tones per beat, the tones are sixteenth notes
+const STEPS_PER_BEAT: f32 = 4.0_f32;
@@ -0,0 +1,117 @@
+use crate::timing::ms_to_ticks;
+
+/// A player for earcons, short note patterns on the VCO output and gate which confirm actions by ear, is represented here
+///
+/// Each earcon is a series of notes, each held for a short time with the gate high and followed by a gap with the gate
+/// low. The player is advanced by ticks so the rest of the firmware keeps running while it plays.
+pub struct EarconPlayer {
+    note_ticks: u32,
+    gap_ticks: u32,
+    earcon: Option<Earcon>,
+    step: u8,
+    ticks_in_step: u32,
+}
+
+/// The earcons which can be played are represented here
+///
+/// * `Saved` - a rising major arpeggio, something was saved
+///
+/// * `Error` - a falling diminished arpeggio, something went wrong
+///
+/// * `Count(n)` - `n` beeps on the same note, such as for picking the `n`th option of a setting
+#[derive(Clone, Copy)]
+pub enum Earcon {
+    Saved,
+    Error,
+    Count(u8),
+}
+
+impl EarconPlayer {
+    /// `EarconPlayer::new(r)` is a new silent earcon player which will be ticked at `r` hertz
+    pub fn new(tick_rate_hz: u32) -> Self {
+        Self {
+            note_ticks: ms_to_ticks(NOTE_MS, tick_rate_hz),
+            gap_ticks: ms_to_ticks(GAP_MS, tick_rate_hz),
+            earcon: None,
+            step: 0,
+            ticks_in_step: 0,
+        }
+    }
+
+    /// `player.play(e)` starts playing the earcon `e`, cutting off any earcon already playing
+    pub fn play(&mut self, earcon: Earcon) {
+        self.earcon = Some(earcon).filter(|&e| 0 < num_steps(e));
+        self.step = 0;
+        self.ticks_in_step = 0;
+    }
+
+    /// `player.tick()` advances the earcon by one tick
+    ///
+    /// It is required to call this function at the tick rate given when the player was created.
+    pub fn tick(&mut self) {
+        let earcon = match self.earcon {
+            Some(earcon) => earcon,
+            None => return,
+        };
+
+        self.ticks_in_step += 1;
+        if self.note_ticks + self.gap_ticks <= self.ticks_in_step {
+            self.ticks_in_step = 0;
+            self.step += 1;
+            if num_steps(earcon) <= self.step {
+                self.earcon = None;
+            }
+        }
+    }
+
+    /// `player.is_playing()` is true iff an earcon is playing, so it should be heard in place of the usual signals
+    pub fn is_playing(&self) -> bool {
+        self.earcon.is_some()
+    }
+
+    /// `player.note()` is the note of the current step of the earcon in semitones above 0 volts
+    pub fn note(&self) -> u8 {
+        let idx = self.step as usize;
+        EARCON_BASE_NOTE
+            + match self.earcon {
+                Some(Earcon::Saved) => SAVED_SEMITONES[idx.min(SAVED_SEMITONES.len() - 1)],
+                Some(Earcon::Error) => ERROR_SEMITONES[idx.min(ERROR_SEMITONES.len() - 1)],
+                Some(Earcon::Count(_)) | None => COUNT_SEMITONES,
+            }
+    }
+
+    /// `player.gate()` is the earcon gate, high while each note sounds and low in the gaps between them
+    pub fn gate(&self) -> bool {
+        self.is_playing() && self.ticks_in_step < self.note_ticks
+    }
+}
+
+/// How long each note of an earcon sounds
+const NOTE_MS: u32 = 70;
+
+/// How long the gap after each note of an earcon is, long enough for the envelopes to retrigger
+const GAP_MS: u32 = 50;
+
+/// The lowest earcon note, C4
+const EARCON_BASE_NOTE: u8 = 48;
+
+/// The notes of the saved earcon above the base note
+const SAVED_SEMITONES: [u8; 4] = [0, 4, 7, 12];
+
+/// The notes of the error earcon above the base note
+const ERROR_SEMITONES: [u8; 4] = [12, 9, 6, 3];
+
+/// The note of the count earcon above the base note
+const COUNT_SEMITONES: u8 = 12;
+
+/// The most beeps a count earcon plays, so that a large count can't hold up the outputs for long
+const MAX_COUNT: u8 = 16;
+
+/// `num_steps(e)` is the number of notes in the earcon `e`
+fn num_steps(earcon: Earcon) -> u8 {
+    match earcon {
+        Earcon::Saved => SAVED_SEMITONES.len() as u8,
+        Earcon::Error => ERROR_SEMITONES.len() as u8,
+        Earcon::Count(n) => n.min(MAX_COUNT),
+    }
+}
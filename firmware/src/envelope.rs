@@ -0,0 +1,261 @@
+/// An envelope generator with exponential segments is represented here
+///
+/// The segments are shaped like an analog envelope, each one is a one-pole filter heading towards a target a little
+/// past the segment's end so that it finishes in the set time. How far past the end is in proportion to the range the
+/// segment covers, so a decay to a high sustain level or a release from part way up takes the set time too. The attack
+/// curves over like a charging capacitor while the decay and release fall off quickly at first and then slow down.
+pub struct Envelope {
+    tick_rate_hz: f32,
+    mode: EnvelopeMode,
+    sustain: f32,
+
+    attack_coeff: f32,
+    decay_coeff: f32,
+    release_coeff: f32,
+
+    stage: Stage,
+    value: f32,
+    segment_start: f32,
+    prev_gate: bool,
+}
+
+/// The envelope can be a full ADSR, or an AR which rises to the top and holds there until the gate falls
+#[derive(Clone, Copy)]
+pub enum EnvelopeMode {
+    Adsr,
+    Ar,
+}
+
+/// The stages of the envelope are represented here
+#[derive(Clone, Copy, PartialEq)]
+enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+impl Envelope {
+    /// `Envelope::new(r)` is a new idle ADSR envelope which will be ticked at `r` hertz
+    pub fn new(tick_rate_hz: u32) -> Self {
+        let mut env = Self {
+            tick_rate_hz: tick_rate_hz as f32,
+            mode: EnvelopeMode::Adsr,
+            sustain: 0.5_f32,
+
+            attack_coeff: 0.0_f32,
+            decay_coeff: 0.0_f32,
+            release_coeff: 0.0_f32,
+
+            stage: Stage::Idle,
+            value: 0.0_f32,
+            segment_start: 0.0_f32,
+            prev_gate: false,
+        };
+        env.set_attack_ms(10);
+        env.set_decay_ms(200);
+        env.set_release_ms(300);
+        env
+    }
+
+    /// `env.set_mode(m)` sets whether the envelope is an ADSR or an AR
+    pub fn set_mode(&mut self, mode: EnvelopeMode) {
+        self.mode = mode;
+    }
+
+    /// `env.set_attack_ms(t)` sets the attack time to `t` milliseconds
+    pub fn set_attack_ms(&mut self, attack_ms: u32) {
+        self.attack_coeff = self.segment_coeff(attack_ms, ATTACK_TARGET_RATIO);
+    }
+
+    /// `env.set_decay_ms(t)` sets the decay time to `t` milliseconds
+    pub fn set_decay_ms(&mut self, decay_ms: u32) {
+        self.decay_coeff = self.segment_coeff(decay_ms, DECAY_RELEASE_TARGET_RATIO);
+    }
+
+    /// `env.set_sustain(s)` sets the sustain level to `s`, clamped to `[0.0, 1.0]`
+    pub fn set_sustain(&mut self, sustain: f32) {
+        self.sustain = sustain.clamp(0.0_f32, 1.0_f32);
+    }
+
+    /// `env.set_release_ms(t)` sets the release time to `t` milliseconds
+    pub fn set_release_ms(&mut self, release_ms: u32) {
+        self.release_coeff = self.segment_coeff(release_ms, DECAY_RELEASE_TARGET_RATIO);
+    }
+
+    /// `env.tick(g)` advances the envelope by one tick, where `g` is the current state of the gate
+    ///
+    /// Each rising edge of the gate starts the attack from the current level, and each falling edge starts the release.
+    /// It is required to call this function at the tick rate given when the envelope was created.
+    pub fn tick(&mut self, gate: bool) {
+        if gate && !self.prev_gate {
+            self.stage = Stage::Attack;
+            self.segment_start = self.value;
+        } else if !gate && self.prev_gate {
+            self.stage = Stage::Release;
+            self.segment_start = self.value;
+        }
+        self.prev_gate = gate;
+
+        // the AR holds at the top, the same as an ADSR with full sustain
+        let sustain = match self.mode {
+            EnvelopeMode::Adsr => self.sustain,
+            EnvelopeMode::Ar => 1.0_f32,
+        };
+
+        match self.stage {
+            Stage::Idle => self.value = 0.0_f32,
+            Stage::Attack => {
+                let target =
+                    1.0_f32 + ATTACK_TARGET_RATIO * segment_range(1.0_f32 - self.segment_start);
+                self.value = target + (self.value - target) * self.attack_coeff;
+                if 1.0_f32 <= self.value {
+                    self.value = 1.0_f32;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                // the decay always starts from the top
+                let target =
+                    sustain - DECAY_RELEASE_TARGET_RATIO * segment_range(1.0_f32 - sustain);
+                self.value = target + (self.value - target) * self.decay_coeff;
+                if self.value <= sustain {
+                    self.value = sustain;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain => self.value = sustain,
+            Stage::Release => {
+                let target = -DECAY_RELEASE_TARGET_RATIO * segment_range(self.segment_start);
+                self.value = target + (self.value - target) * self.release_coeff;
+                if self.value <= 0.0_f32 {
+                    self.value = 0.0_f32;
+                    self.stage = Stage::Idle;
+                }
+            }
+        }
+    }
+
+    /// `env.value()` is the current level of the envelope in `[0.0, 1.0]`
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// `env.segment_coeff(t, r)` is the one-pole coefficient for a segment which takes `t` milliseconds to cover its
+    /// full range, when heading for a target past the end of the segment by `r` times the range
+    fn segment_coeff(&self, time_ms: u32, target_ratio: f32) -> f32 {
+        let num_ticks = (time_ms as f32 * self.tick_rate_hz / 1_000.0_f32).max(1.0_f32);
+        libm::expf(-libm::logf((1.0_f32 + target_ratio) / target_ratio) / num_ticks)
+    }
+}
+
+/// `segment_range(r)` is the range `r` of a segment, but no smaller than `MIN_SEGMENT_RANGE`
+fn segment_range(range: f32) -> f32 {
+    range.max(MIN_SEGMENT_RANGE)
+}
+
+/// Segments covering less than this range finish early, so that the target is never lost in the float precision
+const MIN_SEGMENT_RANGE: f32 = 0.001_f32;
+
+/// How far past the top the attack aims, larger values make the attack straighter
+const ATTACK_TARGET_RATIO: f32 = 0.3_f32;
+
+/// How far past the bottom the decay and release aim, smaller values make them more exponential
+const DECAY_RELEASE_TARGET_RATIO: f32 = 0.001_f32;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `ticks_until(env, g, done)` is the number of ticks with the gate `g` until `done` holds for the envelope `env`
+    fn ticks_until(env: &mut Envelope, gate: bool, done: impl Fn(&Envelope) -> bool) -> u32 {
+        let mut ticks = 0;
+        while !done(env) && ticks < 10_000 {
+            env.tick(gate);
+            ticks += 1;
+        }
+        ticks
+    }
+
+    /// `envelope(s)` is an ADSR ticked at 1 kHz with sustain `s`, so ticks are milliseconds
+    fn envelope(sustain: f32) -> Envelope {
+        let mut env = Envelope::new(1_000);
+        env.set_attack_ms(20);
+        env.set_decay_ms(100);
+        env.set_sustain(sustain);
+        env.set_release_ms(200);
+        env
+    }
+
+    /// `assert_near(t, expected)` checks that a segment took `expected` ticks, give or take one for rounding
+    fn assert_near(ticks: u32, expected: u32) {
+        assert!(
+            (ticks as i32 - expected as i32).abs() <= 1,
+            "took {} ticks, expected {}",
+            ticks,
+            expected
+        );
+    }
+
+    #[test]
+    fn attack_takes_the_set_time() {
+        let mut env = envelope(0.5_f32);
+        assert_near(ticks_until(&mut env, true, |e| e.stage == Stage::Decay), 20);
+        assert_eq!(env.value(), 1.0_f32);
+    }
+
+    #[test]
+    fn decay_takes_the_set_time_to_any_sustain() {
+        for &sustain in &[0.0_f32, 0.5_f32, 0.9_f32] {
+            let mut env = envelope(sustain);
+            ticks_until(&mut env, true, |e| e.stage == Stage::Decay);
+            assert_near(
+                ticks_until(&mut env, true, |e| e.stage == Stage::Sustain),
+                100,
+            );
+            assert_eq!(env.value(), sustain);
+        }
+    }
+
+    #[test]
+    fn release_takes_the_set_time_from_any_level() {
+        for &sustain in &[1.0_f32, 0.5_f32, 0.1_f32] {
+            let mut env = envelope(sustain);
+            ticks_until(&mut env, true, |e| e.stage == Stage::Sustain);
+            assert_near(
+                ticks_until(&mut env, false, |e| e.stage == Stage::Idle),
+                200,
+            );
+        }
+
+        // released part way through the attack
+        let mut env = envelope(0.5_f32);
+        for _ in 0..5 {
+            env.tick(true);
+        }
+        assert_near(
+            ticks_until(&mut env, false, |e| e.stage == Stage::Idle),
+            200,
+        );
+    }
+
+    #[test]
+    fn retriggered_attack_takes_the_set_time() {
+        let mut env = envelope(0.5_f32);
+        ticks_until(&mut env, true, |e| e.stage == Stage::Sustain);
+        env.tick(false);
+        assert_near(ticks_until(&mut env, true, |e| e.stage == Stage::Decay), 20);
+    }
+
+    #[test]
+    fn ar_holds_at_the_top() {
+        let mut env = envelope(0.2_f32);
+        env.set_mode(EnvelopeMode::Ar);
+        ticks_until(&mut env, true, |e| e.stage == Stage::Sustain);
+        for _ in 0..100 {
+            env.tick(true);
+        }
+        assert_eq!(env.value(), 1.0_f32);
+    }
+}
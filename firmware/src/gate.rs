@@ -0,0 +1,343 @@
+use crate::timing::ms_to_ticks;
+
+/// A gate processor which shapes the gate signal according to the selected gate mode is represented here
+pub struct GateProcessor {
+    mode: GateMode,
+    tick_rate_hz: u32,
+    retrigger_ticks: u32,
+    trigger_ticks: u32,
+    min_gate_ticks: u32,
+    min_gate_ticks_remaining: u32,
+    prev_gate_in: bool,
+    ticks_remaining: u32,
+    retrigger_pending: bool,
+}
+
+/// There are three ways to shape the gate
+///
+/// * `Legato` - the gate follows the input gate, moving to a new note does not retrigger the envelopes
+///
+/// * `Retrigger` - the gate follows the input gate, but drops low for a short blip whenever the note changes
+///
+/// * `Trigger` - the gate is a fixed width pulse at each new note
+#[derive(Clone, Copy)]
+pub enum GateMode {
+    Legato,
+    Retrigger,
+    Trigger,
+}
+
+/// There are five ways to merge the ribbon and MIDI gates
+///
+/// * `Or` - either the ribbon or a MIDI note opens the gate
+///
+/// * `RibbonOnly` - only the ribbon opens the gate, MIDI notes only set the pitch
+///
+/// * `MidiOnly` - only MIDI notes open the gate, the ribbon only sets the pitch
+///
+/// * `And` - the ribbon opens the gate, but only while a MIDI note is held
+///
+/// * `MidiGatesRibbonBends` - MIDI notes open the gate and set the pitch, the ribbon is a relative pitch-bend strip
+#[derive(Clone, Copy)]
+pub enum GateMerge {
+    Or,
+    RibbonOnly,
+    MidiOnly,
+    And,
+    MidiGatesRibbonBends,
+}
+
+impl GateProcessor {
+    /// `GateProcessor::new(r)` is a new legato gate processor which will be ticked at `r` hertz
+    pub fn new(tick_rate_hz: u32) -> Self {
+        Self {
+            mode: GateMode::Legato,
+            tick_rate_hz,
+            retrigger_ticks: ms_to_ticks(DEFAULT_RETRIGGER_MS, tick_rate_hz),
+            trigger_ticks: ms_to_ticks(DEFAULT_TRIGGER_MS, tick_rate_hz),
+            min_gate_ticks: ms_to_ticks(DEFAULT_MIN_GATE_MS, tick_rate_hz),
+            min_gate_ticks_remaining: 0,
+            prev_gate_in: false,
+            ticks_remaining: 0,
+            retrigger_pending: false,
+        }
+    }
+
+    /// `gp.set_mode(m)` sets the gate mode to `m`
+    pub fn set_mode(&mut self, mode: GateMode) {
+        self.mode = mode;
+        self.ticks_remaining = 0;
+    }
+
+    /// `gp.set_retrigger_ms(ms)` sets the width of the low blip used in `Retrigger` mode to `ms` milliseconds
+    pub fn set_retrigger_ms(&mut self, ms: u32) {
+        self.retrigger_ticks = ms_to_ticks(ms, self.tick_rate_hz);
+    }
+
+    /// `gp.set_trigger_ms(ms)` sets the width of the pulse used in `Trigger` mode to `ms` milliseconds
+    pub fn set_trigger_ms(&mut self, ms: u32) {
+        self.trigger_ticks = ms_to_ticks(ms, self.tick_rate_hz);
+    }
+
+    /// `gp.set_min_gate_ms(ms)` sets the shortest time that the gate stays open after a note-on to `ms` milliseconds
+    ///
+    /// A quick tap on the ribbon still opens the gate long enough for the envelopes to respond.
+    pub fn set_min_gate_ms(&mut self, ms: u32) {
+        self.min_gate_ticks = ms_to_ticks(ms, self.tick_rate_hz);
+    }
+
+    /// `gp.retrigger()` forces the next tick to be treated as a new note, even in `Legato` mode
+    pub fn retrigger(&mut self) {
+        self.retrigger_pending = true;
+    }
+
+    /// `gp.process(g, n)` is the shaped gate, given the raw gate `g` and whether the note just changed `n`
+    ///
+    /// It is required to call this function at the tick rate given when the processor was created.
+    ///
+    /// # Arguments:
+    ///
+    /// * `gate_in` - the raw gate, high while a note is held
+    ///
+    /// * `note_changed` - true iff the note moved to a new note since the last tick
+    pub fn process(&mut self, gate_in: bool, note_changed: bool) -> bool {
+        let note_on = gate_in && !self.prev_gate_in;
+        self.prev_gate_in = gate_in;
+
+        // a quick tap still holds the gate open for the minimum length
+        if note_on {
+            self.min_gate_ticks_remaining = self.min_gate_ticks;
+        }
+        let gate_in = gate_in || 0 < self.min_gate_ticks_remaining;
+        self.min_gate_ticks_remaining = self.min_gate_ticks_remaining.saturating_sub(1);
+
+        // legato mode ignores note changes, but a forced retrigger applies in every mode
+        let new_note = match self.mode {
+            GateMode::Legato => false,
+            _ => note_changed,
+        } || self.retrigger_pending;
+        self.retrigger_pending = false;
+
+        self.ticks_remaining = self.ticks_remaining.saturating_sub(1);
+
+        match self.mode {
+            GateMode::Legato | GateMode::Retrigger => {
+                // a fresh note-on already has a rising edge, only new notes during a held note need the blip
+                if gate_in && new_note && !note_on {
+                    self.ticks_remaining = self.retrigger_ticks;
+                }
+                gate_in && self.ticks_remaining == 0
+            }
+            GateMode::Trigger => {
+                if note_on || (gate_in && new_note) {
+                    self.ticks_remaining = self.trigger_ticks;
+                }
+                0 < self.ticks_remaining
+            }
+        }
+    }
+}
+
+/// `merge(m, r, g)` is the ribbon gate `r` and the MIDI gate `g` merged according to the merge mode `m`
+pub fn merge(merge: GateMerge, ribbon_gate: bool, midi_gate: bool) -> bool {
+    match merge {
+        GateMerge::Or => ribbon_gate || midi_gate,
+        GateMerge::RibbonOnly => ribbon_gate,
+        GateMerge::MidiOnly | GateMerge::MidiGatesRibbonBends => midi_gate,
+        GateMerge::And => ribbon_gate && midi_gate,
+    }
+}
+
+/// `note_changed(m, r, g)` is true iff the note changed for the gate, given whether the ribbon note changed `r` and
+/// whether the MIDI note changed `g`, with the gates merged according to the merge mode `m`
+///
+/// Only the notes of a source which opens the gate count, so sliding on the ribbon doesn't retrigger a gate held by
+/// MIDI, and MIDI notes don't retrigger a ribbon gate.
+pub fn note_changed(merge: GateMerge, ribbon_changed: bool, midi_changed: bool) -> bool {
+    match merge {
+        GateMerge::Or | GateMerge::And => ribbon_changed || midi_changed,
+        GateMerge::RibbonOnly => ribbon_changed,
+        GateMerge::MidiOnly | GateMerge::MidiGatesRibbonBends => midi_changed,
+    }
+}
+
+/// The default width of the retrigger blip
+const DEFAULT_RETRIGGER_MS: u32 = 5;
+
+/// The default width of the trigger pulse
+const DEFAULT_TRIGGER_MS: u32 = 10;
+
+/// The default minimum gate length
+const DEFAULT_MIN_GATE_MS: u32 = 20;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `run(gp, ins)` is the shaped gate for each of the raw gates and note changes `ins`
+    fn run(gp: &mut GateProcessor, ins: &[(bool, bool)]) -> [bool; 32] {
+        let mut outs = [false; 32];
+        for (out, &(gate_in, note_changed)) in outs.iter_mut().zip(ins.iter()) {
+            *out = gp.process(gate_in, note_changed);
+        }
+        outs
+    }
+
+    /// `gate_processor(m)` is a gate processor in mode `m` ticked at 1 kHz, so ticks are milliseconds
+    fn gate_processor(mode: GateMode) -> GateProcessor {
+        let mut gp = GateProcessor::new(1_000);
+        gp.set_mode(mode);
+        gp.set_retrigger_ms(3);
+        gp.set_trigger_ms(4);
+        gp.set_min_gate_ms(1);
+        gp
+    }
+
+    /// A held note which changes to a new note at tick 10
+    fn held_note_changing() -> [(bool, bool); 32] {
+        let mut ins = [(true, false); 32];
+        ins[0] = (false, false);
+        ins[10] = (true, true);
+        ins
+    }
+
+    #[test]
+    fn legato_ignores_note_changes() {
+        let mut gp = gate_processor(GateMode::Legato);
+        let outs = run(&mut gp, &held_note_changing());
+        assert!(!outs[0]);
+        assert!(outs[1..].iter().all(|&g| g));
+    }
+
+    #[test]
+    fn legato_retriggers_on_a_jump() {
+        let mut gp = gate_processor(GateMode::Legato);
+        let ins = [(true, false); 32];
+        run(&mut gp, &ins[..5]);
+        gp.retrigger();
+        let outs = run(&mut gp, &ins);
+        assert_eq!(outs[..4], [false, false, false, true]);
+        assert!(outs[3..].iter().all(|&g| g));
+    }
+
+    #[test]
+    fn retrigger_blips_low_on_note_change() {
+        let mut gp = gate_processor(GateMode::Retrigger);
+        let outs = run(&mut gp, &held_note_changing());
+        assert!(outs[1..10].iter().all(|&g| g));
+        assert!(outs[10..13].iter().all(|&g| !g));
+        assert!(outs[13..].iter().all(|&g| g));
+    }
+
+    #[test]
+    fn retrigger_doesnt_blip_a_fresh_note() {
+        let mut gp = gate_processor(GateMode::Retrigger);
+        let mut ins = [(true, false); 32];
+        ins[0] = (true, true);
+        let outs = run(&mut gp, &ins);
+        assert!(outs.iter().all(|&g| g));
+    }
+
+    #[test]
+    fn trigger_pulses_at_each_new_note() {
+        let mut gp = gate_processor(GateMode::Trigger);
+        let outs = run(&mut gp, &held_note_changing());
+        let pulse = [
+            false, true, true, true, true, false, false, false, false, false,
+        ];
+        assert_eq!(outs[..10], pulse);
+        assert_eq!(outs[10..14], [true; 4]);
+        assert!(outs[14..].iter().all(|&g| !g));
+    }
+
+    #[test]
+    fn trigger_pulses_on_a_jump() {
+        let mut gp = gate_processor(GateMode::Trigger);
+        let ins = [(true, false); 32];
+        run(&mut gp, &ins[..10]);
+        gp.retrigger();
+        let outs = run(&mut gp, &ins);
+        assert_eq!(outs[..5], [true, true, true, true, false]);
+    }
+
+    #[test]
+    fn trigger_pulse_length_follows_the_setting() {
+        let mut gp = gate_processor(GateMode::Trigger);
+        gp.set_trigger_ms(7);
+        let mut ins = [(false, false); 32];
+        ins[0] = (true, false);
+        let outs = run(&mut gp, &ins);
+        assert_eq!(outs.iter().filter(|&&g| g).count(), 7);
+        assert!(outs[..7].iter().all(|&g| g));
+    }
+
+    #[test]
+    fn quick_taps_last_the_minimum_gate() {
+        // the finger only touches the ribbon for 3 milliseconds
+        let mut ins = [(false, false); 32];
+        for gate_in in ins[..3].iter_mut() {
+            *gate_in = (true, false);
+        }
+        for &mode in &[GateMode::Legato, GateMode::Retrigger] {
+            let mut gp = gate_processor(mode);
+            gp.set_min_gate_ms(20);
+            let outs = run(&mut gp, &ins);
+            assert!(outs[..20].iter().all(|&g| g));
+            assert!(outs[20..].iter().all(|&g| !g));
+        }
+    }
+
+    #[test]
+    fn long_notes_ignore_the_minimum_gate() {
+        let mut ins = [(false, false); 32];
+        for gate_in in ins[..25].iter_mut() {
+            *gate_in = (true, false);
+        }
+        let mut gp = gate_processor(GateMode::Legato);
+        gp.set_min_gate_ms(20);
+        let outs = run(&mut gp, &ins);
+        assert!(outs[..25].iter().all(|&g| g));
+        assert!(outs[25..].iter().all(|&g| !g));
+    }
+
+    #[test]
+    fn merge_modes() {
+        assert!(merge(GateMerge::Or, true, false));
+        assert!(!merge(GateMerge::RibbonOnly, false, true));
+        assert!(merge(GateMerge::MidiOnly, false, true));
+        assert!(!merge(GateMerge::And, true, false));
+        assert!(merge(GateMerge::MidiGatesRibbonBends, false, true));
+    }
+
+    /// `changes(m)` is whether the note changed for the gate with merge mode `m` for a ribbon note change alone, a MIDI
+    /// note change alone, and no change
+    fn changes(merge: GateMerge) -> [bool; 3] {
+        [
+            note_changed(merge, true, false),
+            note_changed(merge, false, true),
+            note_changed(merge, false, false),
+        ]
+    }
+
+    #[test]
+    fn note_changes_follow_the_merge_mode() {
+        assert_eq!(changes(GateMerge::Or), [true, true, false]);
+        assert_eq!(changes(GateMerge::RibbonOnly), [true, false, false]);
+        assert_eq!(changes(GateMerge::MidiOnly), [false, true, false]);
+        assert_eq!(changes(GateMerge::And), [true, true, false]);
+        assert_eq!(
+            changes(GateMerge::MidiGatesRibbonBends),
+            [false, true, false]
+        );
+    }
+
+    #[test]
+    fn ribbon_slides_dont_retrigger_a_midi_gate() {
+        let mut gp = gate_processor(GateMode::Retrigger);
+        let mut ins = [(true, false); 32];
+        ins[0] = (false, false);
+        ins[10] = (true, note_changed(GateMerge::MidiOnly, true, false));
+        let outs = run(&mut gp, &ins);
+        assert!(outs[1..].iter().all(|&g| g));
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{board::Switch3wayState, timing::ms_to_ticks};
+
+/// A recognizer for gestures made with the 3-way mode switch is represented here
+///
+/// The switch is debounced, then each change of position is compared against the gestures below. The switch keeps
+/// selecting the pitch mode as usual, a gesture only adds a secondary function on top.
+pub struct GestureRecognizer {
+    debounce_ticks: u32,
+    flick_ticks: u32,
+    power_up_ticks: u32,
+
+    raw_position: Switch3wayState,
+    raw_stable_ticks: u32,
+
+    position: Switch3wayState,
+    prev_position: Switch3wayState,
+    ticks_in_position: u32,
+    ticks_since_power_up: u32,
+    power_up_position: Option<Switch3wayState>,
+
+    gesture: Option<Gesture>,
+}
+
+/// The gestures which can be made with the mode switch are represented here
+///
+/// * `Flick(p)` - the switch is flicked from position `p` to the middle and back within `FLICK_MS`
+///
+/// * `Nudge(p)` - the switch is nudged from the middle to position `p` and back within `FLICK_MS`
+///
+/// * `PowerUpHold(p)` - the switch is held in position `p` through power-up and then moved to the middle within
+///   `POWER_UP_MS`, so that simply powering up with the switch up or down doesn't count
+#[derive(Clone, Copy)]
+pub enum Gesture {
+    Flick(Switch3wayState),
+    Nudge(Switch3wayState),
+    PowerUpHold(Switch3wayState),
+}
+
+impl GestureRecognizer {
+    /// `GestureRecognizer::new(r, p)` is a new gesture recognizer polled at `r` hertz, with the switch in position `p`
+    /// at power-up
+    pub fn new(tick_rate_hz: u32, position: Switch3wayState) -> Self {
+        Self {
+            debounce_ticks: ms_to_ticks(DEBOUNCE_MS, tick_rate_hz),
+            flick_ticks: ms_to_ticks(FLICK_MS, tick_rate_hz),
+            power_up_ticks: ms_to_ticks(POWER_UP_MS, tick_rate_hz),
+
+            raw_position: position,
+            raw_stable_ticks: 0,
+
+            position,
+            prev_position: position,
+            ticks_in_position: 0,
+            ticks_since_power_up: 0,
+            power_up_position: if position == Switch3wayState::Middle {
+                None
+            } else {
+                Some(position)
+            },
+
+            gesture: None,
+        }
+    }
+
+    /// `recognizer.poll(p)` updates the recognizer with the raw switch position `p`
+    ///
+    /// It is required to call this function at the tick rate given when the recognizer was created.
+    pub fn poll(&mut self, raw_position: Switch3wayState) {
+        self.ticks_in_position = self.ticks_in_position.saturating_add(1);
+        self.ticks_since_power_up = self.ticks_since_power_up.saturating_add(1);
+        if self.power_up_ticks < self.ticks_since_power_up {
+            self.power_up_position = None;
+        }
+
+        // the position only changes once the raw reading has settled
+        if raw_position == self.raw_position {
+            self.raw_stable_ticks = self.raw_stable_ticks.saturating_add(1);
+        } else {
+            self.raw_position = raw_position;
+            self.raw_stable_ticks = 1;
+        }
+        if self.raw_stable_ticks < self.debounce_ticks || raw_position == self.position {
+            return;
+        }
+
+        // a flick or nudge quickly returns the switch to where it came from
+        let from_position = self.position;
+        let returned =
+            raw_position == self.prev_position && self.ticks_in_position <= self.flick_ticks;
+
+        self.prev_position = from_position;
+        self.position = raw_position;
+        self.ticks_in_position = 0;
+
+        let gesture = match self.power_up_position.take() {
+            Some(power_up_position) if raw_position == Switch3wayState::Middle => {
+                Some(Gesture::PowerUpHold(power_up_position))
+            }
+            Some(_) => None,
+            None if returned && from_position == Switch3wayState::Middle => {
+                Some(Gesture::Flick(raw_position))
+            }
+            None if returned && raw_position == Switch3wayState::Middle => {
+                Some(Gesture::Nudge(from_position))
+            }
+            None => None,
+        };
+
+        if gesture.is_some() {
+            self.gesture = gesture;
+            // the move which ends a gesture isn't also the start of the next one
+            self.prev_position = raw_position;
+        }
+    }
+
+    /// `recognizer.take_gesture()` is the gesture recognized since the last call, if any, self clearing
+    pub fn take_gesture(&mut self) -> Option<Gesture> {
+        self.gesture.take()
+    }
+}
+
+/// The raw switch reading must hold steady for this long before it counts as a new position
+const DEBOUNCE_MS: u32 = 50;
+
+/// The longest the switch can rest in the middle for a flick
+const FLICK_MS: u32 = 500;
+
+/// The longest after power-up that the switch can be moved to the middle for a power-up hold
+const POWER_UP_MS: u32 = 2_000;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Switch3wayState::{Down, Middle, Up};
+
+    /// The poll rate used in the tests, so that ticks are milliseconds
+    const RATE_HZ: u32 = 1_000;
+
+    /// `hold(r, p, ms)` polls the recognizer `r` with the switch in position `p` for `ms` milliseconds, and is every
+    /// gesture recognized along the way
+    fn hold(
+        recognizer: &mut GestureRecognizer,
+        position: Switch3wayState,
+        ms: u32,
+    ) -> [Option<Gesture>; 4] {
+        let mut gestures = [None; 4];
+        let mut num_gestures = 0;
+        for _ in 0..ms {
+            recognizer.poll(position);
+            if let Some(gesture) = recognizer.take_gesture() {
+                gestures[num_gestures] = Some(gesture);
+                num_gestures += 1;
+            }
+        }
+        gestures
+    }
+
+    /// `settled(p)` is a recognizer in position `p` which is past the power-up window
+    fn settled(position: Switch3wayState) -> GestureRecognizer {
+        let mut recognizer = GestureRecognizer::new(RATE_HZ, Middle);
+        hold(&mut recognizer, Middle, POWER_UP_MS + 1);
+        hold(&mut recognizer, position, 1_000);
+        recognizer
+    }
+
+    #[test]
+    fn bounces_are_debounced() {
+        let mut recognizer = settled(Up);
+        hold(&mut recognizer, Middle, DEBOUNCE_MS - 1);
+        assert!(recognizer.position == Up);
+        let gestures = hold(&mut recognizer, Up, 1_000);
+        assert!(matches!(gestures, [None, None, None, None]));
+
+        hold(&mut recognizer, Middle, DEBOUNCE_MS);
+        assert!(recognizer.position == Middle);
+    }
+
+    #[test]
+    fn flicks_must_be_quick() {
+        // the time in the middle counts from when the switch settles there to when it settles back
+        let mut recognizer = settled(Up);
+        hold(&mut recognizer, Middle, FLICK_MS);
+        let gestures = hold(&mut recognizer, Up, DEBOUNCE_MS);
+        assert!(matches!(
+            gestures,
+            [Some(Gesture::Flick(Up)), None, None, None]
+        ));
+
+        let mut recognizer = settled(Up);
+        hold(&mut recognizer, Middle, FLICK_MS + 1);
+        let gestures = hold(&mut recognizer, Up, DEBOUNCE_MS);
+        assert!(matches!(gestures, [None, None, None, None]));
+    }
+
+    #[test]
+    fn power_up_holds_must_be_released_in_time() {
+        // the switch settles in the middle one debounce time after it is moved there
+        let mut recognizer = GestureRecognizer::new(RATE_HZ, Down);
+        hold(&mut recognizer, Down, POWER_UP_MS - DEBOUNCE_MS);
+        let gestures = hold(&mut recognizer, Middle, DEBOUNCE_MS);
+        assert!(matches!(
+            gestures,
+            [Some(Gesture::PowerUpHold(Down)), None, None, None]
+        ));
+
+        let mut recognizer = GestureRecognizer::new(RATE_HZ, Down);
+        hold(&mut recognizer, Down, POWER_UP_MS - DEBOUNCE_MS + 1);
+        let gestures = hold(&mut recognizer, Middle, DEBOUNCE_MS);
+        assert!(matches!(gestures, [None, None, None, None]));
+    }
+
+    #[test]
+    fn powering_up_in_the_middle_is_not_a_gesture() {
+        let mut recognizer = GestureRecognizer::new(RATE_HZ, Middle);
+        let gestures = hold(&mut recognizer, Middle, POWER_UP_MS + 1);
+        assert!(matches!(gestures, [None, None, None, None]));
+    }
+
+    #[test]
+    fn nudge_from_the_middle() {
+        let mut recognizer = settled(Middle);
+        hold(&mut recognizer, Up, 200);
+        let gestures = hold(&mut recognizer, Middle, 200);
+        assert!(matches!(
+            gestures,
+            [Some(Gesture::Nudge(Up)), None, None, None]
+        ));
+
+        hold(&mut recognizer, Down, 200);
+        let gestures = hold(&mut recognizer, Middle, 200);
+        assert!(matches!(
+            gestures,
+            [Some(Gesture::Nudge(Down)), None, None, None]
+        ));
+    }
+
+    #[test]
+    fn flicks_are_not_nudges() {
+        let mut recognizer = settled(Up);
+        hold(&mut recognizer, Middle, 200);
+        let gestures = hold(&mut recognizer, Up, 200);
+        assert!(matches!(
+            gestures,
+            [Some(Gesture::Flick(Up)), None, None, None]
+        ));
+
+        // moving on to the middle right after the flick is just a mode change
+        let gestures = hold(&mut recognizer, Middle, 1_000);
+        assert!(matches!(gestures, [None, None, None, None]));
+    }
+}
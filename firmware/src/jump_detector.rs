@@ -0,0 +1,81 @@
+/// A detector for sudden jumps in the ribbon position while the finger stays down is represented here
+///
+/// When a second finger lands on the softpot the reading jumps to a point between the two fingers without the first
+/// finger ever lifting, so the ribbon controller never reports a new press. No human can slide that fast, so a large
+/// change over a few milliseconds is treated as a new note. The jump is reported once the reading has settled at the
+/// new position.
+pub struct JumpDetector {
+    threshold: f32,
+    history: [f32; WINDOW_LEN],
+    next: usize,
+    samples_since_settled: usize,
+    jumping: bool,
+    jumped: bool,
+}
+
+impl JumpDetector {
+    /// `JumpDetector::new(t)` is a new jump detector which reports jumps larger than `t`
+    ///
+    /// # Arguments:
+    ///
+    /// * `threshold` - the smallest change in ribbon position over the detection window which counts as a jump, as a
+    ///   fraction of the whole ribbon length
+    pub fn new(threshold: f32) -> Self {
+        Self {
+            threshold,
+            history: [0.0_f32; WINDOW_LEN],
+            next: 0,
+            samples_since_settled: 0,
+            jumping: false,
+            jumped: false,
+        }
+    }
+
+    /// `detector.poll(p, f)` updates the detector with the ribbon position `p`, `f` is true iff the finger is down
+    ///
+    /// It is required to call this function at the fast ribbon sample rate, the timing constants assume 1kHz.
+    pub fn poll(&mut self, position: f32, finger_is_pressing: bool) {
+        if !finger_is_pressing {
+            self.samples_since_settled = 0;
+            self.jumping = false;
+            return;
+        }
+
+        let prev = self.history[(self.next + WINDOW_LEN - 1) % WINDOW_LEN];
+        self.history[self.next] = position;
+        self.next = (self.next + 1) % WINDOW_LEN;
+        self.samples_since_settled += 1;
+
+        // the window must only hold samples from after the finger landed or settled from the last jump
+        if self.samples_since_settled <= WINDOW_LEN {
+            return;
+        }
+
+        if self.jumping {
+            let step = position - prev;
+            if step.max(-step) < SETTLED_STEP {
+                self.jumping = false;
+                self.jumped = true;
+                // forget the old position so the same jump isn't detected twice
+                self.samples_since_settled = 0;
+            }
+        } else {
+            // the oldest sample in the window
+            let delta = position - self.history[self.next];
+            self.jumping = self.threshold < delta.max(-delta);
+        }
+    }
+
+    /// `detector.take_jump()` is true iff a jump was detected since the last call, self clearing
+    pub fn take_jump(&mut self) -> bool {
+        let jumped = self.jumped;
+        self.jumped = false;
+        jumped
+    }
+}
+
+/// The number of samples over which the change in position is measured
+const WINDOW_LEN: usize = 5;
+
+/// Sample to sample changes smaller than this mean that the reading has settled after a jump
+const SETTLED_STEP: f32 = 0.001_f32;
@@ -0,0 +1,250 @@
+/// A latch which holds the last ribbon values after the finger is lifted is represented here
+///
+/// When the latch is enabled the values from the last touch are held until the ribbon is touched again, which makes
+/// it possible to play sustained drones without keeping a finger on the ribbon. The gate can optionally be held too.
+pub struct Latch<const N: usize> {
+    enabled: bool,
+    hold_gate: bool,
+    held_vals: Option<[f32; N]>,
+    prev_finger_is_pressing: bool,
+}
+
+impl<const N: usize> Latch<N> {
+    /// `Latch::new()` is a new disabled latch which holds the values but not the gate
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            hold_gate: false,
+            held_vals: None,
+            prev_finger_is_pressing: false,
+        }
+    }
+
+    /// `latch.set_enabled(e)` enables or disables the latch, disabling it releases any held values
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.held_vals = None;
+        }
+    }
+
+    /// `latch.set_hold_gate(g)` sets whether the latch holds the gate along with the values
+    pub fn set_hold_gate(&mut self, hold_gate: bool) {
+        self.hold_gate = hold_gate;
+    }
+
+    /// `latch.toggle()` flips the latch between enabled and disabled
+    pub fn toggle(&mut self) {
+        self.set_enabled(!self.enabled);
+    }
+
+    /// `latch.process(p, vs)` is the live values `vs` while the finger is pressing, or the held values otherwise
+    ///
+    /// # Arguments:
+    ///
+    /// * `finger_is_pressing` - true iff the finger is currently pressing the ribbon
+    ///
+    /// * `vals` - the live values derived from the ribbon
+    ///
+    /// # Returns:
+    ///
+    /// * `vals` if the finger is pressing, the latch is disabled, or nothing has been played yet, else the values from
+    ///   the moment the finger lifted
+    pub fn process(&mut self, finger_is_pressing: bool, vals: [f32; N]) -> [f32; N] {
+        let just_released = self.prev_finger_is_pressing && !finger_is_pressing;
+        self.prev_finger_is_pressing = finger_is_pressing;
+
+        if !self.enabled {
+            return vals;
+        }
+
+        // the values on the tick that the finger lifts are held, the release freeze has already removed any droop
+        if finger_is_pressing || just_released {
+            self.held_vals = Some(vals);
+            vals
+        } else {
+            self.held_vals.unwrap_or(vals)
+        }
+    }
+
+    /// `latch.gate(p)` is the ribbon gate, which stays high after the finger lifts if the latch is holding the gate
+    pub fn gate(&self, finger_is_pressing: bool) -> bool {
+        finger_is_pressing || (self.hold_gate && self.held_vals.is_some())
+    }
+}
+
+/// A double-tap gesture detector for the ribbon is represented here
+///
+/// A double-tap is two short touches in quick succession, detected at the moment the second touch lands.
+pub struct DoubleTapDetector {
+    max_tap_ticks: u32,
+    max_gap_ticks: u32,
+    ticks_in_state: u32,
+    state: TapState,
+}
+
+/// The states of the double-tap detector are represented here
+#[derive(Clone, Copy, PartialEq)]
+enum TapState {
+    Idle,
+    FirstTouch,
+    Gap,
+    WaitForLift,
+}
+
+impl DoubleTapDetector {
+    /// `DoubleTapDetector::new(r)` is a new double-tap detector which will be polled at `r` hertz
+    pub fn new(poll_rate_hz: u32) -> Self {
+        // the longest a tap can be held, and the longest time between the two taps
+        const MAX_TAP_MS: u32 = 200;
+        const MAX_GAP_MS: u32 = 250;
+
+        Self {
+            max_tap_ticks: MAX_TAP_MS * poll_rate_hz / 1_000,
+            max_gap_ticks: MAX_GAP_MS * poll_rate_hz / 1_000,
+            ticks_in_state: 0,
+            state: TapState::Idle,
+        }
+    }
+
+    /// `detector.poll(p)` is true iff a double-tap was just completed, `p` is true iff the finger is pressing
+    ///
+    /// It is required to call this function at the poll rate given when the detector was created.
+    pub fn poll(&mut self, finger_is_pressing: bool) -> bool {
+        self.ticks_in_state = self.ticks_in_state.saturating_add(1);
+
+        let (next_state, double_tapped) = match (self.state, finger_is_pressing) {
+            (TapState::Idle, true) => (TapState::FirstTouch, false),
+            // held too long to be a tap
+            (TapState::FirstTouch, true) if self.max_tap_ticks < self.ticks_in_state => {
+                (TapState::WaitForLift, false)
+            }
+            (TapState::FirstTouch, false) => (TapState::Gap, false),
+            (TapState::Gap, false) if self.max_gap_ticks < self.ticks_in_state => {
+                (TapState::Idle, false)
+            }
+            (TapState::Gap, true) => (TapState::WaitForLift, true),
+            // wait for the finger to lift before looking for a new double-tap
+            (TapState::WaitForLift, false) => (TapState::Idle, false),
+            (state, _) => (state, false),
+        };
+
+        if next_state != self.state {
+            self.state = next_state;
+            self.ticks_in_state = 0;
+        }
+
+        double_tapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `play(latch, ps)` is the first value out of the latch for each finger pressing `p` and live value `v` in `ps`
+    fn play(latch: &mut Latch<1>, presses: &[(bool, f32)]) -> [f32; 8] {
+        let mut outs = [0.0_f32; 8];
+        for (out, &(finger_is_pressing, val)) in outs.iter_mut().zip(presses.iter()) {
+            *out = latch.process(finger_is_pressing, [val])[0];
+        }
+        outs
+    }
+
+    #[test]
+    fn disabled_latch_passes_the_live_values() {
+        let mut latch = Latch::<1>::new();
+        let outs = play(&mut latch, &[(true, 0.3), (false, 0.1), (false, 0.0)]);
+        assert_eq!(outs[..3], [0.3, 0.1, 0.0]);
+        assert!(!latch.gate(false));
+    }
+
+    #[test]
+    fn latch_holds_the_pitch_after_release() {
+        let mut latch = Latch::<1>::new();
+        latch.set_enabled(true);
+        let outs = play(
+            &mut latch,
+            &[
+                (true, 0.3),
+                (true, 0.4),
+                (false, 0.4),
+                (false, 0.0),
+                (false, 0.7),
+            ],
+        );
+        assert_eq!(outs[..5], [0.3, 0.4, 0.4, 0.4, 0.4]);
+
+        // the next touch takes over from the held values
+        let outs = play(&mut latch, &[(true, 0.6), (false, 0.6), (false, 0.0)]);
+        assert_eq!(outs[..3], [0.6, 0.6, 0.6]);
+    }
+
+    #[test]
+    fn latch_holds_the_gate_only_when_set_to() {
+        let mut latch = Latch::<1>::new();
+        latch.set_enabled(true);
+        play(&mut latch, &[(true, 0.3), (false, 0.0)]);
+        assert!(!latch.gate(false));
+        assert!(latch.gate(true));
+
+        latch.set_hold_gate(true);
+        assert!(latch.gate(false));
+    }
+
+    #[test]
+    fn disabling_the_latch_releases_it() {
+        let mut latch = Latch::<1>::new();
+        latch.set_hold_gate(true);
+        latch.set_enabled(true);
+        play(&mut latch, &[(true, 0.3), (false, 0.0)]);
+        latch.toggle();
+        assert!(!latch.gate(false));
+        assert_eq!(play(&mut latch, &[(false, 0.1)])[0], 0.1);
+    }
+
+    /// `taps(d, ms)` polls the double-tap detector `d` at 1 kHz with a touch for each `(down, up)` in milliseconds in
+    /// `ms`, and is true iff a double-tap was detected
+    fn taps(detector: &mut DoubleTapDetector, touches: &[(u32, u32)]) -> bool {
+        let mut double_tapped = false;
+        for &(down_ms, up_ms) in touches.iter() {
+            for _ in 0..down_ms {
+                double_tapped |= detector.poll(true);
+            }
+            for _ in 0..up_ms {
+                double_tapped |= detector.poll(false);
+            }
+        }
+        double_tapped
+    }
+
+    #[test]
+    fn quick_taps_are_a_double_tap() {
+        let mut detector = DoubleTapDetector::new(1_000);
+        assert!(taps(&mut detector, &[(50, 100), (50, 100)]));
+        // the longest taps and gap that still count
+        assert!(taps(&mut detector, &[(200, 250), (50, 100)]));
+    }
+
+    #[test]
+    fn a_slow_second_tap_is_rejected() {
+        let mut detector = DoubleTapDetector::new(1_000);
+        assert!(!taps(&mut detector, &[(50, 252), (50, 100)]));
+    }
+
+    #[test]
+    fn long_touches_are_not_taps() {
+        for &held_ms in &[202, 500] {
+            let mut detector = DoubleTapDetector::new(1_000);
+            assert!(!taps(&mut detector, &[(held_ms, 100), (50, 100)]));
+        }
+    }
+
+    #[test]
+    fn one_double_tap_per_touch() {
+        // the third tap starts over rather than completing another double-tap with the second
+        let mut detector = DoubleTapDetector::new(1_000);
+        assert!(taps(&mut detector, &[(50, 100), (50, 100)]));
+        assert!(!taps(&mut detector, &[(50, 100)]));
+    }
+}
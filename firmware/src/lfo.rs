@@ -0,0 +1,320 @@
+use core::f32::consts::PI;
+
+use crate::rng::Rng;
+
+/// A low frequency oscillator with a selection of waveforms is represented here
+///
+/// The LFO can run freely at its set rate or follow the MIDI clock. It can fade in after each gate-on, and the phase
+/// can optionally be reset by each gate-on so that every note gets the same modulation.
+pub struct Lfo {
+    tick_rate_hz: f32,
+    waveform: LfoWaveform,
+    rate_hz: f32,
+    sync: LfoSync,
+    delay_ticks: u32,
+    key_reset: bool,
+
+    phase: f32,
+    prev_gate: bool,
+    ticks_since_gate_on: u32,
+
+    ticks_since_clock: u32,
+    ticks_per_clock: u32,
+    clocks_this_cycle: u32,
+
+    random_from: f32,
+    random_to: f32,
+    rng: Rng,
+}
+
+/// The waveforms which the LFO can produce are represented here
+///
+/// * `SmoothRandom` - glides smoothly to a new random level once per cycle
+#[derive(Clone, Copy)]
+pub enum LfoWaveform {
+    Sine,
+    Triangle,
+    SawUp,
+    SawDown,
+    Square,
+    SmoothRandom,
+}
+
+/// The AUX/Delay channel carries either the attenuated ribbon signal or the LFO
+#[derive(Clone, Copy)]
+pub enum AuxSource {
+    Ribbon,
+    Lfo,
+}
+
+/// The LFO either runs freely at its set rate, or completes one cycle every so many MIDI clocks
+#[derive(Clone, Copy, PartialEq)]
+pub enum LfoSync {
+    Free,
+    MidiClocks(u32),
+}
+
+impl Lfo {
+    /// `Lfo::new(r)` is a new free running sine LFO at 1 hertz, which will be ticked at `r` hertz
+    pub fn new(tick_rate_hz: u32) -> Self {
+        Self {
+            tick_rate_hz: tick_rate_hz as f32,
+            waveform: LfoWaveform::Sine,
+            rate_hz: 1.0_f32,
+            sync: LfoSync::Free,
+            delay_ticks: 0,
+            key_reset: false,
+
+            phase: 0.0_f32,
+            prev_gate: false,
+            // the LFO is at full depth until the first gate-on
+            ticks_since_gate_on: u32::MAX,
+
+            ticks_since_clock: u32::MAX,
+            ticks_per_clock: 1,
+            clocks_this_cycle: 0,
+
+            random_from: 0.0_f32,
+            random_to: 0.0_f32,
+            rng: Rng::new(0x8765_4321),
+        }
+    }
+
+    /// `lfo.set_waveform(w)` sets the LFO waveform to `w`
+    pub fn set_waveform(&mut self, waveform: LfoWaveform) {
+        self.waveform = waveform;
+    }
+
+    /// `lfo.set_rate_hz(f)` sets the free running rate to `f` hertz, clamped to `[MIN_RATE_HZ, MAX_RATE_HZ]`
+    pub fn set_rate_hz(&mut self, rate_hz: f32) {
+        self.rate_hz = rate_hz.clamp(MIN_RATE_HZ, MAX_RATE_HZ);
+    }
+
+    /// `lfo.set_sync(s)` sets whether the LFO runs freely or follows the MIDI clock
+    pub fn set_sync(&mut self, sync: LfoSync) {
+        self.sync = sync;
+    }
+
+    /// `lfo.set_delay_ms(ms)` sets the time in milliseconds the LFO takes to fade in after each gate-on
+    pub fn set_delay_ms(&mut self, delay_ms: u32) {
+        self.delay_ticks = (delay_ms as f32 * self.tick_rate_hz / 1_000.0_f32) as u32;
+    }
+
+    /// `lfo.set_key_reset(r)` sets whether each gate-on resets the phase of the LFO
+    pub fn set_key_reset(&mut self, key_reset: bool) {
+        self.key_reset = key_reset;
+    }
+
+    /// `lfo.midi_clock()` tells the LFO that a MIDI clock was received
+    pub fn midi_clock(&mut self) {
+        self.ticks_per_clock = self.ticks_since_clock.clamp(1, CLOCK_TIMEOUT_TICKS);
+        self.ticks_since_clock = 0;
+        if let LfoSync::MidiClocks(clocks_per_cycle) = self.sync {
+            self.clocks_this_cycle = (self.clocks_this_cycle + 1) % clocks_per_cycle.max(1);
+        }
+    }
+
+    /// `lfo.midi_start()` lines the LFO cycle up with the start of the MIDI song
+    pub fn midi_start(&mut self) {
+        self.clocks_this_cycle = 0;
+    }
+
+    /// `lfo.tick(g)` advances the LFO by one tick, where `g` is the current state of the gate
+    ///
+    /// It is required to call this function at the tick rate given when the LFO was created.
+    pub fn tick(&mut self, gate: bool) {
+        self.ticks_since_clock = self.ticks_since_clock.saturating_add(1);
+        self.ticks_since_gate_on = self.ticks_since_gate_on.saturating_add(1);
+
+        let gate_on = gate && !self.prev_gate;
+        self.prev_gate = gate;
+        if gate_on {
+            self.ticks_since_gate_on = 0;
+        }
+
+        let prev_phase = self.phase;
+        self.phase = match self.sync {
+            // follow the clock while it is running, the phase is interpolated between clocks
+            LfoSync::MidiClocks(clocks_per_cycle)
+                if self.ticks_since_clock < CLOCK_TIMEOUT_TICKS =>
+            {
+                let between_clocks =
+                    (self.ticks_since_clock as f32 / self.ticks_per_clock as f32).min(1.0_f32);
+                (self.clocks_this_cycle as f32 + between_clocks) / clocks_per_cycle.max(1) as f32
+            }
+            _ if gate_on && self.key_reset => 0.0_f32,
+            _ => {
+                let phase = self.phase + self.rate_hz / self.tick_rate_hz;
+                phase - (phase as u32) as f32
+            }
+        };
+
+        // the smooth random waveform picks a new level at the start of each cycle
+        if self.phase < prev_phase || (gate_on && self.key_reset) {
+            self.random_from = self.random_to;
+            self.random_to = self.rng.next_u32() as f32 / u32::MAX as f32 * 2.0_f32 - 1.0_f32;
+        }
+    }
+
+    /// `lfo.value()` is the current output of the LFO in `[-1.0, 1.0]`, scaled down while it fades in
+    pub fn value(&self) -> f32 {
+        let p = self.phase;
+        let raw = match self.waveform {
+            LfoWaveform::Sine => libm::sinf(2.0_f32 * PI * p),
+            LfoWaveform::Triangle => {
+                if p < 0.5_f32 {
+                    4.0_f32 * p - 1.0_f32
+                } else {
+                    3.0_f32 - 4.0_f32 * p
+                }
+            }
+            LfoWaveform::SawUp => 2.0_f32 * p - 1.0_f32,
+            LfoWaveform::SawDown => 1.0_f32 - 2.0_f32 * p,
+            LfoWaveform::Square => {
+                if p < 0.5_f32 {
+                    1.0_f32
+                } else {
+                    -1.0_f32
+                }
+            }
+            LfoWaveform::SmoothRandom => {
+                // a raised cosine glide from the old level to the new one
+                let t = 0.5_f32 - 0.5_f32 * libm::cosf(PI * p);
+                self.random_from + (self.random_to - self.random_from) * t
+            }
+        };
+
+        raw * self.fade_in()
+    }
+
+    /// `lfo.fade_in()` is the depth of the LFO in `[0.0, 1.0]` as it fades in after the last gate-on
+    fn fade_in(&self) -> f32 {
+        if self.delay_ticks == 0 {
+            1.0_f32
+        } else {
+            (self.ticks_since_gate_on as f32 / self.delay_ticks as f32).min(1.0_f32)
+        }
+    }
+}
+
+/// The slowest free running LFO rate
+pub const MIN_RATE_HZ: f32 = 0.01_f32;
+
+/// The fastest free running LFO rate
+///
+/// The LFO is ticked at the 300 hertz output rate, so at this rate each cycle is only 6 steps. Fast LFO rates are
+/// stepped rather than smooth, which is left as is for buzzy effects instead of capping the rate lower.
+pub const MAX_RATE_HZ: f32 = 50.0_f32;
+
+/// If no MIDI clock is received for this many ticks the LFO goes back to running freely
+const CLOCK_TIMEOUT_TICKS: u32 = 150;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `wraps(lfo, n)` is the ticks in the first `n` at which the phase of the saw up LFO `lfo` wraps around
+    ///
+    /// If there are more than 16 wraps only the first 16 are kept. A MIDI clock is sent to the LFO every 5 ticks when it
+    /// is synced to the clock.
+    fn wraps(lfo: &mut Lfo, n: u32) -> heapless::Vec<u32, 16> {
+        let mut wraps = heapless::Vec::new();
+        let mut prev_value = lfo.value();
+        for i in 0..n {
+            if lfo.sync != LfoSync::Free && i % 5 == 0 {
+                lfo.midi_clock();
+            }
+            lfo.tick(false);
+            if lfo.value() < prev_value {
+                wraps.push(i).ok();
+            }
+            prev_value = lfo.value();
+        }
+        wraps
+    }
+
+    fn saw_lfo() -> Lfo {
+        let mut lfo = Lfo::new(300);
+        lfo.set_waveform(LfoWaveform::SawUp);
+        lfo
+    }
+
+    #[test]
+    fn phase_advances_by_the_rate_over_the_tick_rate() {
+        let mut lfo = saw_lfo();
+        lfo.set_rate_hz(10.0_f32);
+        for _ in 0..15 {
+            lfo.tick(false);
+        }
+        // half way through the cycle
+        assert!(lfo.value().abs() < 1.0e-3_f32);
+
+        let mut lfo = saw_lfo();
+        lfo.set_rate_hz(10.0_f32);
+        assert_eq!(wraps(&mut lfo, 305).len(), 10);
+    }
+
+    #[test]
+    fn rate_is_clamped() {
+        let mut lfo = saw_lfo();
+        lfo.set_rate_hz(1_000.0_f32);
+        // 6 ticks per cycle at the fastest rate
+        assert_eq!(wraps(&mut lfo, 63).len(), 10);
+        assert!(wraps(&mut lfo, 63).windows(2).all(|w| w[1] - w[0] == 6));
+
+        let mut lfo = saw_lfo();
+        lfo.set_rate_hz(0.0_f32);
+        lfo.tick(false);
+        let expected = 2.0_f32 * MIN_RATE_HZ / 300.0_f32 - 1.0_f32;
+        assert!((lfo.value() - expected).abs() < 1.0e-6_f32);
+    }
+
+    #[test]
+    fn waveform_shapes() {
+        let expected = [
+            (LfoWaveform::Sine, 1.0_f32, -1.0_f32),
+            (LfoWaveform::Triangle, 0.0_f32, 0.0_f32),
+            (LfoWaveform::SawUp, -0.5_f32, 0.5_f32),
+            (LfoWaveform::SawDown, 0.5_f32, -0.5_f32),
+            (LfoWaveform::Square, 1.0_f32, -1.0_f32),
+        ];
+        for &(waveform, at_quarter, at_three_quarters) in expected.iter() {
+            // an eighth of a cycle per tick
+            let mut lfo = Lfo::new(8);
+            lfo.set_waveform(waveform);
+            for _ in 0..2 {
+                lfo.tick(false);
+            }
+            assert!((lfo.value() - at_quarter).abs() < 1.0e-5_f32);
+            for _ in 0..4 {
+                lfo.tick(false);
+            }
+            assert!((lfo.value() - at_three_quarters).abs() < 1.0e-5_f32);
+        }
+    }
+
+    #[test]
+    fn clock_sync_period() {
+        // one cycle per quarter note of 24 clocks, with a clock every 5 ticks
+        let mut lfo = saw_lfo();
+        lfo.set_sync(LfoSync::MidiClocks(24));
+        let wraps = wraps(&mut lfo, 3 * 24 * 5 + 2);
+        assert_eq!(wraps.len(), 3);
+        assert!(wraps.windows(2).all(|w| w[1] - w[0] == 24 * 5));
+    }
+
+    #[test]
+    fn runs_freely_when_the_clock_stops() {
+        let mut lfo = saw_lfo();
+        lfo.set_rate_hz(10.0_f32);
+        lfo.set_sync(LfoSync::MidiClocks(24));
+        lfo.midi_clock();
+        for _ in 0..CLOCK_TIMEOUT_TICKS {
+            lfo.tick(false);
+        }
+        let before = lfo.value();
+        lfo.tick(false);
+        let step = (lfo.value() - before + 2.0_f32) % 2.0_f32;
+        assert!((step - 2.0_f32 / 30.0_f32).abs() < 1.0e-4_f32);
+    }
+}
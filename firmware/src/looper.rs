@@ -0,0 +1,196 @@
+/// A looper which records the ribbon gestures and plays them back in a loop is represented here
+///
+/// The ribbon position and finger state are recorded once per tick. Each sample is packed into 16 bits, the top bit is
+/// the finger state and the rest is the position, so `LOOPER_CAPACITY` samples fit in a small slice of the RAM.
+///
+/// The looper is driven by a single "arm" control which steps through idle, armed, recording, and playing. Recording
+/// begins at the first touch after arming, so the loop starts right on the gesture.
+pub struct Looper {
+    samples: [u16; LOOPER_CAPACITY],
+    len: usize,
+    play_idx: usize,
+    state: LooperState,
+    prev_playback_pressing: bool,
+
+    ticks_since_clock: u32,
+    clocks_this_beat: u32,
+    ticks_this_beat: u32,
+    ticks_per_beat: Option<u32>,
+}
+
+/// The states of the looper are represented here
+#[derive(Clone, Copy, PartialEq)]
+enum LooperState {
+    Idle,
+    Armed,
+    Recording,
+    Playing,
+}
+
+/// A single sample of ribbon playback is represented here
+#[derive(Clone, Copy)]
+pub struct LoopSample {
+    pub position: f32,
+    pub finger_is_pressing: bool,
+    pub finger_just_pressed: bool,
+}
+
+/// While the loop plays, a live finger on the ribbon can either transpose the loop or play the VCF channel
+#[derive(Clone, Copy)]
+pub enum LooperOverdub {
+    Transpose,
+    LiveVcf,
+}
+
+impl Looper {
+    /// `Looper::new()` is a new idle looper with nothing recorded
+    pub fn new() -> Self {
+        Self {
+            samples: [0; LOOPER_CAPACITY],
+            len: 0,
+            play_idx: 0,
+            state: LooperState::Idle,
+            prev_playback_pressing: false,
+
+            ticks_since_clock: u32::MAX,
+            clocks_this_beat: 0,
+            ticks_this_beat: 0,
+            ticks_per_beat: None,
+        }
+    }
+
+    /// `looper.arm()` steps the looper to its next state
+    ///
+    /// Idle loopers become armed, armed loopers go back to idle, recording loopers start playing, and playing loopers
+    /// stop.
+    pub fn arm(&mut self) {
+        self.state = match self.state {
+            LooperState::Idle => LooperState::Armed,
+            LooperState::Armed => LooperState::Idle,
+            LooperState::Recording => {
+                self.finish_recording();
+                LooperState::Playing
+            }
+            LooperState::Playing => LooperState::Idle,
+        };
+    }
+
+    /// `looper.midi_clock()` tells the looper that a MIDI clock was received, there are 24 clocks per beat
+    ///
+    /// When the clock is running the loop length snaps to a whole number of beats.
+    pub fn midi_clock(&mut self) {
+        self.ticks_since_clock = 0;
+        self.clocks_this_beat += 1;
+        if MIDI_CLOCKS_PER_BEAT <= self.clocks_this_beat {
+            self.ticks_per_beat = Some(self.ticks_this_beat);
+            self.clocks_this_beat = 0;
+            self.ticks_this_beat = 0;
+        }
+    }
+
+    /// `looper.tick(p, f, j)` records the live ribbon position `p` and finger state `f` if the looper is recording
+    ///
+    /// `j` is true iff the finger was just pressed, which starts the recording if the looper is armed. It is required
+    /// to call this function at a steady rate, the same rate that the loop will be played back at.
+    pub fn tick(&mut self, position: f32, finger_is_pressing: bool, finger_just_pressed: bool) {
+        self.ticks_since_clock = self.ticks_since_clock.saturating_add(1);
+        self.ticks_this_beat = self.ticks_this_beat.saturating_add(1);
+        if CLOCK_TIMEOUT_TICKS < self.ticks_since_clock {
+            // the clock stopped, loops are free length until it comes back
+            self.ticks_per_beat = None;
+            self.clocks_this_beat = 0;
+        }
+
+        if self.state == LooperState::Armed && finger_just_pressed {
+            self.state = LooperState::Recording;
+            self.len = 0;
+        }
+
+        match self.state {
+            LooperState::Recording => {
+                self.samples[self.len] = pack(position, finger_is_pressing);
+                self.len += 1;
+                if self.len == LOOPER_CAPACITY {
+                    // out of room, start playing what we have
+                    self.arm();
+                }
+            }
+            LooperState::Playing => self.play_idx = (self.play_idx + 1) % self.len,
+            _ => (),
+        }
+    }
+
+    /// `looper.playback()` is the current sample of the loop if the looper is playing, else `None`
+    pub fn playback(&mut self) -> Option<LoopSample> {
+        if self.state != LooperState::Playing {
+            self.prev_playback_pressing = false;
+            return None;
+        }
+
+        let (position, finger_is_pressing) = unpack(self.samples[self.play_idx]);
+        let finger_just_pressed = finger_is_pressing && !self.prev_playback_pressing;
+        self.prev_playback_pressing = finger_is_pressing;
+
+        Some(LoopSample {
+            position,
+            finger_is_pressing,
+            finger_just_pressed,
+        })
+    }
+
+    /// `looper.is_playing()` is true iff the looper is playing back a loop
+    pub fn is_playing(&self) -> bool {
+        self.state == LooperState::Playing
+    }
+
+    /// `looper.finish_recording()` trims or pads the loop to a whole number of beats if the MIDI clock is running
+    fn finish_recording(&mut self) {
+        if let Some(ticks_per_beat) = self.ticks_per_beat.filter(|&t| 0 < t) {
+            let ticks_per_beat = ticks_per_beat as usize;
+            let max_beats = LOOPER_CAPACITY / ticks_per_beat;
+            // round to the nearest beat
+            let beats =
+                ((self.len + ticks_per_beat / 2) / ticks_per_beat).clamp(1, max_beats.max(1));
+            let snapped_len = (beats * ticks_per_beat).min(LOOPER_CAPACITY);
+
+            // pad a short loop with the last position and the finger lifted
+            let (last_position, _) = unpack(self.samples[self.len.max(1) - 1]);
+            for sample in self.samples[self.len.min(snapped_len)..snapped_len].iter_mut() {
+                *sample = pack(last_position, false);
+            }
+            self.len = snapped_len;
+        }
+
+        // a loop needs at least one sample, the next tick wraps around to the start of the loop
+        self.len = self.len.max(1);
+        self.play_idx = self.len - 1;
+    }
+}
+
+/// The maximum number of samples in a loop, 10 seconds at 300Hz
+pub const LOOPER_CAPACITY: usize = 3_000;
+
+/// The MIDI clock has 24 pulses per beat
+const MIDI_CLOCKS_PER_BEAT: u32 = 24;
+
+/// If no MIDI clock is received for this many ticks the clock is considered stopped
+const CLOCK_TIMEOUT_TICKS: u32 = 150;
+
+/// The finger state is stored in the top bit of each sample
+const PRESSING_BIT: u16 = 1 << 15;
+
+/// `pack(p, f)` is the position `p` in `[0.0, 1.0]` and finger state `f` packed into a single sample
+fn pack(position: f32, finger_is_pressing: bool) -> u16 {
+    let position = (position.clamp(0.0_f32, 1.0_f32) * (PRESSING_BIT - 1) as f32) as u16;
+    if finger_is_pressing {
+        position | PRESSING_BIT
+    } else {
+        position
+    }
+}
+
+/// `unpack(s)` is the position and finger state packed into the sample `s`
+fn unpack(sample: u16) -> (f32, bool) {
+    let position = (sample & !PRESSING_BIT) as f32 / (PRESSING_BIT - 1) as f32;
+    (position, sample & PRESSING_BIT != 0)
+}
@@ -1,18 +1,63 @@
-#![no_std]
-#![no_main]
+// the pure modules are unit tested on the host, where the test harness needs std and its own main
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 
+mod arpeggiator;
 mod board;
+mod chord_strum;
+mod earcon;
+mod envelope;
+mod gate;
+mod gesture;
+mod jump_detector;
+mod latch;
+mod lfo;
+mod looper;
+mod midi_out;
+mod midi_parser;
+mod mod_matrix;
+mod release_freeze;
+mod rng;
+mod sequencer;
+mod settings;
+mod slide_speed;
+mod smoothing;
+mod takeover;
+mod timing;
 mod ui;
+mod velocity;
 
 use synth_utils::{mono_midi_receiver, quantizer, ribbon_controller};
 
 use crate::{
-    board::{AdcPin, Board, Dac8164Channel},
-    ui::{LevelPot, PitchMode, UiState},
+    arpeggiator::{ArpRibbonMode, Arpeggiator},
+    board::{AdcPin, Board, Dac8164Channel, Switch3wayState},
+    chord_strum::ChordStrum,
+    earcon::{Earcon, EarconPlayer},
+    envelope::Envelope,
+    gate::{GateMerge, GateProcessor},
+    gesture::Gesture,
+    jump_detector::JumpDetector,
+    latch::{DoubleTapDetector, Latch},
+    lfo::{AuxSource, Lfo},
+    looper::{Looper, LooperOverdub},
+    midi_out::{MidiOutput, RibbonPerformance},
+    midi_parser::{MidiMessage, MidiParser},
+    mod_matrix::{ModMatrix, ModRouting, ModSource, MOD_MATRIX_NUM_WORDS, NUM_MOD_SOURCES},
+    release_freeze::ReleaseFreeze,
+    rng::Rng,
+    sequencer::{Sequencer, SequencerMode, Step, SEQUENCE_NUM_WORDS},
+    settings::Settings,
+    slide_speed::SlideSpeed,
+    smoothing::RibbonSmoother,
+    ui::{LevelPot, PitchMode, UiState, VcoPotMode},
+    velocity::TouchVelocityEstimator,
 };
 
+#[cfg(not(test))]
 use panic_halt as _;
 
+#[cfg(not(test))]
 use cortex_m_rt::entry;
 
 const FAST_RIBBON_SAMPLE_RATE: u32 = board::TIM2_FREQ_HZ;
@@ -28,7 +73,36 @@ const MAIN_RIBBON_NUM_SEMITONES: f32 = 49.0_f32;
 const RIBBON_FUDGE_FACTOR: f32 = quantizer::SEMITONE_WIDTH;
 const MAIN_RIBBON_MAX_VOUT: f32 = MAIN_RIBBON_NUM_SEMITONES / 12.0_f32 + RIBBON_FUDGE_FACTOR;
 
-#[entry]
+// a change in ribbon position larger than this over a few milliseconds means a second finger landed
+const RIBBON_JUMP_THRESHOLD: f32 = 0.04_f32;
+
+// the time constant in seconds used to smooth the slide speed
+const SLIDE_SPEED_SMOOTHING_TIME: f32 = 0.03_f32;
+
+// the ribbon jitter filter is heavy while the finger is still and light while it moves, use the same cutoff for both
+// to get a plain one-pole filter
+const RIBBON_SMOOTHING_MIN_CUTOFF_HZ: f32 = 8.0_f32;
+const RIBBON_SMOOTHING_MAX_CUTOFF_HZ: f32 = 200.0_f32;
+
+// the flash storage page is shared, each kind of saved data has its own words in the page
+const SEQUENCE_STORAGE_OFFSET: usize = 0;
+const MOD_MATRIX_STORAGE_OFFSET: usize = SEQUENCE_STORAGE_OFFSET + SEQUENCE_NUM_WORDS;
+
+// calibration mode steps the VCO, MODOSC, and VCF channels through whole volts along the ribbon, up to this voltage
+const CALIBRATION_MAX_VOLTS: f32 = 4.0_f32;
+
+// the MIDI output is transposed so the bottom of the ribbon plays C2, received MIDI notes are not transposed
+const MIDI_OUT_TRANSPOSE_SEMITONES: f32 = 36.0_f32;
+
+// the most system clock cycles the modulation matrix may take each update before it is bypassed, a small slice of
+// the 266_666 cycles between updates
+const MOD_MATRIX_CYCLE_BUDGET: u32 = 10_000;
+
+// the modulation matrix is only bypassed after running over its budget this many updates in a row, so that a single
+// slow update, such as one stretched by an interrupt, does not bypass it
+const MOD_MATRIX_MAX_OVERRUNS: u32 = 8;
+
+#[cfg_attr(not(test), entry)]
 fn main() -> ! {
     let mut board = Board::init();
     let mut ui = UiState::new();
@@ -48,7 +122,99 @@ fn main() -> ! {
 
     midi_receiver.set_note_priority(mono_midi_receiver::NotePriority::Last);
 
+    let mut midi_parser = MidiParser::new(0);
+
+    // settings without a dedicated panel control are adjusted via MIDI CC
+    let mut settings = Settings::new();
+
+    // the latch holds all four ribbon signals, it is toggled with a double-tap or the sustain pedal
+    let mut latch = Latch::<4>::new();
+    let mut double_tap_detector = DoubleTapDetector::new(board::TIM15_FREQ_HZ);
+
+    // the velocity is estimated when the finger lands and held until the next touch
+    let mut velocity_estimator = TouchVelocityEstimator::new();
+    let mut touch_velocity: f32 = 0.0_f32;
+
+    let mut jump_detector = JumpDetector::new(RIBBON_JUMP_THRESHOLD);
+
+    let mut slide_speed =
+        SlideSpeed::new(FAST_RIBBON_SAMPLE_RATE as f32, SLIDE_SPEED_SMOOTHING_TIME);
+
+    let mut ribbon_smoother = RibbonSmoother::new(
+        FAST_RIBBON_SAMPLE_RATE as f32,
+        RIBBON_SMOOTHING_MIN_CUTOFF_HZ,
+        RIBBON_SMOOTHING_MAX_CUTOFF_HZ,
+    );
+
+    let mut gate_processor = GateProcessor::new(board::TIM15_FREQ_HZ);
+
+    // the ribbon position is frozen at its value from just before the finger lifts
+    let mut release_freeze = ReleaseFreeze::new(0);
+
+    // held MIDI notes can be arpeggiated
+    let mut arp = Arpeggiator::new(board::TIM15_FREQ_HZ);
+
+    // the ribbon zones can strum diatonic chords
+    let mut strum = ChordStrum::new(board::TIM15_FREQ_HZ);
+
+    // ribbon gestures can be recorded and looped, a live finger overdubs the loop
+    let mut looper = Looper::new();
+    let mut transpose_quantizer = quantizer::Quantizer::new();
+
+    // the step sequencer picks up where it left off with the sequence saved in flash, if there is one
+    let mut seq = Sequencer::new(board::TIM15_FREQ_HZ);
+    let mut stored_sequence = [0; SEQUENCE_NUM_WORDS];
+    board.read_storage(SEQUENCE_STORAGE_OFFSET, &mut stored_sequence);
+    if seq.load_words(&stored_sequence) {
+        settings.set_seq_len(seq.len());
+    }
+
+    // the AUX/Delay channel can carry an LFO instead of the ribbon
+    let mut lfo = Lfo::new(board::TIM15_FREQ_HZ);
+
+    // a modulation envelope follows the gate and can be added to any DAC channel
+    let mut env = Envelope::new(board::TIM15_FREQ_HZ);
+
+    // the modulation matrix can replace the fixed routing of the DAC channels, the user preset is loaded from flash
+    let lead_matrix = ModMatrix::lead();
+    let motion_matrix = ModMatrix::motion();
+    let mut user_matrix = ModMatrix::new();
+    let mut stored_matrix = [0; MOD_MATRIX_NUM_WORDS];
+    board.read_storage(MOD_MATRIX_STORAGE_OFFSET, &mut stored_matrix);
+    user_matrix.load_words(&stored_matrix);
+    let mut mod_matrix_overruns: u32 = 0;
+
+    let mut mod_wheel: f32 = 0.0_f32;
+    // the random modulation source is a new random level at each gate-on
+    let mut rng = Rng::new(0x0BAD_5EED);
+    let mut random_level: f32 = 0.0_f32;
+    let mut prev_gate_out = false;
+
+    // earcons on the VCO output and gate confirm actions by ear
+    let mut earcons = EarconPlayer::new(board::TIM15_FREQ_HZ);
+
+    // the ribbon performance can be sent out over MIDI, on the same channel as MIDI is received
+    let mut midi_out = MidiOutput::new(board::TIM15_FREQ_HZ, 0);
+
+    // calibration mode is entered by holding the mode switch up through power-up, and lasts until power-off
+    let mut calibrating = false;
+
+    apply_settings(
+        &settings,
+        &mut ui,
+        &mut gate_processor,
+        &mut release_freeze,
+        &mut arp,
+        &mut strum,
+        &mut seq,
+    );
+    apply_modulation_settings(&settings, &mut lfo, &mut env);
+    apply_midi_out_settings(&settings, &mut midi_out);
+    let mut prev_ribbon_note: i32 = 0;
+    let mut prev_midi_note: u8 = 0;
+
     let mut offset_when_finger_pressed_down: f32 = 0.0_f32;
+    let mut bender_anchor: f32 = 0.0_f32;
 
     // small delay to allow the ribbon voltage to settle before beginning
     board.delay_ms(100);
@@ -57,27 +223,200 @@ fn main() -> ! {
 
     loop {
         if let Some(b) = board.serial_read() {
-            midi_receiver.parse(b)
+            midi_receiver.parse(b);
+
+            match midi_parser.parse(b) {
+                Some(MidiMessage::ControlChange { control, value }) => {
+                    match control {
+                        midi_parser::CC_SUSTAIN_PEDAL => latch.set_enabled(64 <= value),
+                        midi_parser::CC_MOD_WHEEL => mod_wheel = value as f32 / 127.0_f32,
+                        settings::CC_MOD_AMOUNT => user_matrix
+                            .set_amount(settings.mod_cell(), (value as f32 - 64.0_f32) / 63.0_f32),
+                        settings::CC_POT_POSITION => {
+                            ui.set_pot_position(settings.selected_pot(), value as f32 / 127.0_f32)
+                        }
+                        // picking a routing gives a bypassed modulation matrix another chance
+                        settings::CC_MOD_ROUTING => mod_matrix_overruns = 0,
+                        // picking MPE mode again configures the zone again, for a receiver which missed it
+                        settings::CC_MIDI_OUT_MODE => midi_out.reconfigure(),
+                        _ => (),
+                    }
+                    if 64 <= value {
+                        match control {
+                            settings::CC_LOOPER_ARM => looper.arm(),
+                            settings::CC_SEQ_TIE => seq.tie_last_step(),
+                            settings::CC_SEQ_REST => seq.enter_step(Step::REST),
+                            settings::CC_SEQ_SAVE => {
+                                earcons.play(save_earcon(board.update_storage(&[(
+                                    SEQUENCE_STORAGE_OFFSET,
+                                    &seq.to_words()[..],
+                                )])))
+                            }
+                            settings::CC_MOD_SAVE => {
+                                earcons.play(save_earcon(board.update_storage(&[(
+                                    MOD_MATRIX_STORAGE_OFFSET,
+                                    &user_matrix.to_words()[..],
+                                )])))
+                            }
+                            _ => (),
+                        }
+                    }
+                    if settings.handle_cc(control, value) {
+                        apply_settings(
+                            &settings,
+                            &mut ui,
+                            &mut gate_processor,
+                            &mut release_freeze,
+                            &mut arp,
+                            &mut strum,
+                            &mut seq,
+                        );
+                        apply_modulation_settings(&settings, &mut lfo, &mut env);
+                        apply_midi_out_settings(&settings, &mut midi_out);
+                    }
+                }
+                Some(MidiMessage::NoteOn { note }) => arp.note_on(note),
+                Some(MidiMessage::NoteOff { note }) => arp.note_off(note),
+                Some(MidiMessage::Clock) => {
+                    arp.midi_clock();
+                    seq.midi_clock();
+                    looper.midi_clock();
+                    lfo.midi_clock();
+                }
+                Some(MidiMessage::Start) => {
+                    arp.midi_start();
+                    seq.midi_start();
+                    lfo.midi_start();
+                }
+                Some(MidiMessage::Continue) => {
+                    arp.midi_continue();
+                    seq.midi_continue();
+                }
+                Some(MidiMessage::Stop) => {
+                    arp.midi_stop();
+                    seq.midi_stop();
+                }
+                None => (),
+            }
         }
 
         // slow timer for updating UI, reading pots and such
         if board.get_tim6_timeout() {
             ui.update(&mut board);
+
+            // gestures with the mode switch reach the functions which have no panel control of their own, while the
+            // settings menu is open any flick closes it
+            match ui.take_gesture() {
+                Some(Gesture::Flick(_)) if ui.menu_active() => ui.set_menu_active(false),
+                Some(Gesture::Flick(Switch3wayState::Up)) => latch.toggle(),
+                Some(Gesture::Flick(Switch3wayState::Down)) => {
+                    let saved = board.update_storage(&[
+                        (SEQUENCE_STORAGE_OFFSET, &seq.to_words()[..]),
+                        (MOD_MATRIX_STORAGE_OFFSET, &user_matrix.to_words()[..]),
+                    ]);
+                    earcons.play(save_earcon(saved));
+                }
+                Some(Gesture::Nudge(Switch3wayState::Up)) => looper.arm(),
+                Some(Gesture::PowerUpHold(Switch3wayState::Up)) => calibrating = true,
+                Some(Gesture::PowerUpHold(Switch3wayState::Down)) => ui.set_menu_active(true),
+                _ => (),
+            }
+
+            // options picked in the settings menu are handled just like MIDI control changes, and confirmed with one
+            // beep for the first option, two for the second, and so on
+            if let Some(change) = ui.take_menu_change() {
+                earcons.play(Earcon::Count(change.option + 1));
+                if settings.handle_cc(change.control, change.value) {
+                    apply_settings(
+                        &settings,
+                        &mut ui,
+                        &mut gate_processor,
+                        &mut release_freeze,
+                        &mut arp,
+                        &mut strum,
+                        &mut seq,
+                    );
+                    apply_modulation_settings(&settings, &mut lfo, &mut env);
+                    apply_midi_out_settings(&settings, &mut midi_out);
+                }
+            }
         }
 
         // fast timer for polling the ribbon
         if board.get_tim2_timeout() {
             let raw_adc_val = board.read_adc(RIBBON_PIN);
             ribbon.poll(raw_adc_val);
+            velocity_estimator.poll(raw_adc_val);
+            slide_speed.poll(ribbon.value(), ribbon.finger_is_pressing());
+            jump_detector.poll(ribbon.value(), ribbon.finger_is_pressing());
+            // the jump detector and slide speed want the raw position, the played position is smoothed
+            ribbon_smoother.poll(ribbon.value(), ribbon.finger_is_pressing());
+            release_freeze.poll(ribbon_smoother.value(), ribbon.finger_is_pressing());
         }
 
         // timer to update analog and MIDI outputs
         if board.get_tim15_timeout() {
+            // the ribbon position with any droop from the finger lifting removed
+            let live_ribbon_val = release_freeze.value();
+            let live_finger_is_pressing = ribbon.finger_is_pressing();
+            let live_finger_just_pressed = ribbon.finger_just_pressed();
+
+            // while a loop plays it stands in for the finger on the ribbon
+            looper.tick(
+                live_ribbon_val,
+                live_finger_is_pressing,
+                live_finger_just_pressed,
+            );
+            let (ribbon_val, finger_is_pressing, finger_just_pressed) = match looper.playback() {
+                Some(sample) => (
+                    sample.position,
+                    sample.finger_is_pressing,
+                    sample.finger_just_pressed,
+                ),
+                None => (
+                    live_ribbon_val,
+                    live_finger_is_pressing,
+                    live_finger_just_pressed,
+                ),
+            };
+            let overdubbing = looper.is_playing() && live_finger_is_pressing;
+
+            // the arpeggiator takes over the MIDI note and gate while it is running
+            let arp_running = settings.arp_enabled() && arp.is_active();
+            let arp_scrubbing = arp_running
+                && finger_is_pressing
+                && matches!(settings.arp_ribbon_mode(), ArpRibbonMode::Scrub);
+            arp.scrub(if arp_scrubbing {
+                Some(ribbon_val)
+            } else {
+                None
+            });
+            arp.tick();
+
+            // the step sequencer takes over the MIDI note and gate while it plays, each new step retriggers the gate
+            seq.tick();
+            if seq.take_step() {
+                gate_processor.retrigger();
+            }
+
+            let (midi_note, midi_gate) = if seq.is_playing() {
+                (seq.note(), seq.gate())
+            } else if arp_running {
+                (arp.note(), arp.gate())
+            } else {
+                (midi_receiver.note_num(), midi_receiver.gate())
+            };
+
             // expand the ribbon signal to 1volt/octave range
-            let ribbon_as_1v_per_oct = ribbon_to_dac8164_1v_per_oct(ribbon.value());
+            let ribbon_as_1v_per_oct = ribbon_to_dac8164_1v_per_oct(ribbon_val);
 
-            // attenuate the ribbon signals with the front panel controls
-            let vco_ribbon_contrib = ui.attenuate(ribbon_as_1v_per_oct, LevelPot::Vco);
+            // attenuate the ribbon signals with the front panel controls, the VCO pot may instead select the note span
+            let vco_ribbon_contrib = match ui.vco_pot_mode() {
+                VcoPotMode::Attenuate => ui.attenuate(ribbon_as_1v_per_oct, LevelPot::Vco),
+                VcoPotMode::NoteSpan => {
+                    ribbon_to_1v_per_oct_note_span(ribbon_val, ui.vco_span_notes())
+                }
+            };
             let modosc_ribbon_contrib = ui.attenuate(ribbon_as_1v_per_oct, LevelPot::ModOsc);
             let vcf_ribbon_contrib = ui.attenuate(ribbon_as_1v_per_oct, LevelPot::Vcf);
             let delay_ribbon_contrib = ui.attenuate(ribbon_as_1v_per_oct, LevelPot::Delay);
@@ -86,10 +425,46 @@ fn main() -> ! {
             let quantized_vco_ribbon = vco_quantizer
                 .convert(vco_ribbon_contrib + quantizer::HALF_SEMITONE_WIDTH / 2.0_f32);
 
-            let finger_just_pressed = ribbon.finger_just_pressed();
+            // the note under the finger, in semitones above 0 volts
+            let ribbon_note = (quantized_vco_ribbon.stairstep * 12.0_f32 + 0.5_f32) as i32;
+
+            // while recording, each tap on the ribbon enters the quantized note into the sequence
+            if finger_just_pressed && settings.seq_mode() == SequencerMode::Record {
+                seq.enter_step(Step {
+                    note: ribbon_note.clamp(0, 127) as u8,
+                    gate_length: settings.seq_gate_length(),
+                    tie: false,
+                    vcf: if settings.seq_records_vcf() {
+                        Some(vcf_ribbon_contrib)
+                    } else {
+                        None
+                    },
+                });
+            }
+
+            if live_finger_just_pressed {
+                touch_velocity = velocity_estimator.estimate();
+            }
+
+            // a second finger landing makes the ribbon jump without lifting, which counts as a new note, but while a loop
+            // plays the live finger is only overdubbing. Like any ribbon note change it only retriggers a gate the ribbon
+            // opens
+            let finger_jumped = jump_detector.take_jump() && !looper.is_playing();
+            if finger_jumped
+                && settings.jump_retriggers_gate()
+                && gate::note_changed(settings.gate_merge(), true, false)
+            {
+                gate_processor.retrigger();
+            }
+
+            // when MIDI notes gate the synth the ribbon is always a pitch-bend strip
+            let pitch_mode = match settings.gate_merge() {
+                GateMerge::MidiGatesRibbonBends => PitchMode::Bend,
+                _ => ui.pitch_mode(),
+            };
 
-            // the VCO can be one of three modes
-            let vco_ribbon_contrib = match ui.pitch_mode() {
+            // the VCO can be one of four modes
+            let vco_ribbon_contrib = match pitch_mode {
                 // hard-quantize and smooth modes are simple to calculate
                 PitchMode::HardQuantize => quantized_vco_ribbon.stairstep,
                 PitchMode::Smooth => {
@@ -99,10 +474,10 @@ fn main() -> ! {
                 }
                 // assist mode has more going on
                 PitchMode::Assist => {
-                    if finger_just_pressed {
+                    if finger_just_pressed || finger_jumped {
                         // When the user first presses down after having lifted their finger record the offset between the
                         // finger position and the center of the note. We'll use this offset to make sure that it plays
-                        // a nice in-tune note at first-press.
+                        // a nice in-tune note at first-press. A two-finger jump is treated just like a fresh press.
                         offset_when_finger_pressed_down = quantized_vco_ribbon.fraction;
                         // use the stairstep for the first press for a nice in-tune note
                         quantized_vco_ribbon.stairstep
@@ -112,9 +487,82 @@ fn main() -> ! {
                         vco_ribbon_contrib - offset_when_finger_pressed_down
                     }
                 }
+                // bend mode is relative to wherever the finger first landed
+                PitchMode::Bend => {
+                    if finger_just_pressed {
+                        bender_anchor = ribbon_val;
+                    }
+                    if finger_is_pressing {
+                        ribbon_to_bend_1v_per_oct(
+                            ribbon_val - bender_anchor,
+                            settings.bender_range_semitones(),
+                        )
+                    } else {
+                        // the bend snaps back to zero when the finger is lifted
+                        0.0_f32
+                    }
+                }
+            };
+
+            // when the ribbon scrubs the arpeggio it picks the notes instead of transposing them
+            let vco_ribbon_contrib = if arp_scrubbing {
+                0.0_f32
+            } else {
+                vco_ribbon_contrib
             };
 
-            let midi_1v_per_oct = note_num_to_dac8164_1v_per_oct(midi_receiver.note_num())
+            // in strum mode the ribbon zones select chords, and each chord tone retriggers the gate
+            let vco_ribbon_contrib = if settings.strum_enabled() {
+                strum.tick(if finger_is_pressing {
+                    Some(ribbon_val)
+                } else {
+                    None
+                });
+                if strum.take_step() {
+                    gate_processor.retrigger();
+                }
+                note_num_to_dac8164_1v_per_oct(strum.note())
+            } else {
+                vco_ribbon_contrib
+            };
+
+            // a live finger overdubs the playing loop, either transposing it or playing the VCF channel
+            let (vco_ribbon_contrib, vcf_ribbon_contrib) = match settings.looper_overdub() {
+                LooperOverdub::Transpose if overdubbing => {
+                    let transpose = transpose_quantizer.convert(
+                        ribbon_to_dac8164_1v_per_oct(live_ribbon_val)
+                            + quantizer::HALF_SEMITONE_WIDTH / 2.0_f32,
+                    );
+                    (vco_ribbon_contrib + transpose.stairstep, vcf_ribbon_contrib)
+                }
+                LooperOverdub::LiveVcf if overdubbing => (
+                    vco_ribbon_contrib,
+                    ui.attenuate(ribbon_to_dac8164_1v_per_oct(live_ribbon_val), LevelPot::Vcf),
+                ),
+                _ => (vco_ribbon_contrib, vcf_ribbon_contrib),
+            };
+
+            // tapping around the settings menu shouldn't toggle the latch, the detector keeps up even while the
+            // double-tap is turned off so that turning it on doesn't count a stale tap
+            let double_tapped = double_tap_detector.poll(live_finger_is_pressing);
+            if double_tapped && settings.latch_double_tap() && !ui.menu_active() {
+                latch.toggle();
+            }
+
+            // the latch may hold the ribbon signals after the finger lifts
+            latch.set_hold_gate(settings.latch_holds_gate());
+            let [vco_ribbon_contrib, modosc_ribbon_contrib, vcf_ribbon_contrib, delay_ribbon_contrib] =
+                latch.process(
+                    finger_is_pressing,
+                    [
+                        vco_ribbon_contrib,
+                        modosc_ribbon_contrib,
+                        vcf_ribbon_contrib,
+                        delay_ribbon_contrib,
+                    ],
+                );
+
+            let midi_1v_per_oct = note_num_to_dac8164_1v_per_oct(midi_note)
                 + (midi_receiver.pitch_bend() * 2.0_f32 / 12.0_f32);
 
             // VCO always gets un-attenuated MIDI note information so it plays in-tune
@@ -129,14 +577,182 @@ fn main() -> ! {
             let final_vcf_ribbon = vcf_ribbon_contrib + vcf_midi_contrib;
             let final_delay_ribbon = delay_ribbon_contrib + delay_midi_contrib;
 
+            // moving to a new quantized ribbon note or a new MIDI note may retrigger the gate
+            let ribbon_note_changed = finger_is_pressing && ribbon_note != prev_ribbon_note;
+            prev_ribbon_note = ribbon_note;
+
+            let midi_note_changed = midi_gate && midi_note != prev_midi_note;
+            prev_midi_note = midi_note;
+
+            // the gate follows the ribbon and MIDI signals merged as selected by the user
+            let gate_in = gate::merge(
+                settings.gate_merge(),
+                latch.gate(finger_is_pressing),
+                midi_gate,
+            );
+            let note_changed = gate::note_changed(
+                settings.gate_merge(),
+                ribbon_note_changed,
+                midi_note_changed,
+            );
+            let gate_out = gate_processor.process(gate_in, note_changed);
+
+            // the LFO is keyed by the merged gate, the envelope by the gate output so retriggers restart it
+            lfo.tick(gate_in);
+            env.tick(gate_out);
+            if gate_out && !prev_gate_out {
+                random_level = rng.next_u32() as f32 / u32::MAX as f32;
+            }
+            prev_gate_out = gate_out;
+
+            // the delay pot sets the depth when the AUX/Delay channel carries the LFO
+            let final_delay_ribbon = match settings.aux_source() {
+                AuxSource::Ribbon => final_delay_ribbon,
+                AuxSource::Lfo => {
+                    // a bipolar delay pot swings the LFO around the center voltage, otherwise the LFO is unipolar
+                    let lfo_volts = if ui.is_bipolar(LevelPot::Delay) {
+                        lfo.value() * board::DAC8164_MAX_VOLTS / 2.0_f32
+                    } else {
+                        (lfo.value() + 1.0_f32) / 2.0_f32 * board::DAC8164_MAX_VOLTS
+                    };
+                    ui.attenuate(lfo_volts, LevelPot::Delay)
+                }
+            };
+
+            // bipolar pots center their channels on the bipolar center voltage
+            let mut dac_outputs = [
+                (final_vco_ribbon, Dac8164Channel::A),
+                (
+                    final_modosc_ribbon + ui.center_offset(LevelPot::ModOsc),
+                    Dac8164Channel::B,
+                ),
+                (
+                    final_vcf_ribbon + ui.center_offset(LevelPot::Vcf),
+                    Dac8164Channel::C,
+                ),
+                (
+                    final_delay_ribbon + ui.center_offset(LevelPot::Delay),
+                    Dac8164Channel::D,
+                ),
+            ];
+
+            // a modulation matrix preset replaces the fixed routing, unless it keeps running over its cycle budget
+            let mod_matrix = match settings.mod_routing() {
+                ModRouting::Fixed => None,
+                ModRouting::Lead => Some(&lead_matrix),
+                ModRouting::Motion => Some(&motion_matrix),
+                ModRouting::User => Some(&user_matrix),
+            };
+            if let Some(mod_matrix) =
+                mod_matrix.filter(|_| mod_matrix_overruns < MOD_MATRIX_MAX_OVERRUNS)
+            {
+                let start_cycles = board.cycle_count();
+
+                let mut mod_sources = [0.0_f32; NUM_MOD_SOURCES];
+                mod_sources[ModSource::RibbonPosition as usize] = ribbon_as_1v_per_oct;
+                mod_sources[ModSource::QuantizedPitch as usize] = quantized_vco_ribbon.stairstep;
+                mod_sources[ModSource::MidiNote as usize] =
+                    note_num_to_dac8164_1v_per_oct(midi_note);
+                mod_sources[ModSource::Velocity as usize] =
+                    touch_velocity * board::DAC8164_MAX_VOLTS;
+                mod_sources[ModSource::PitchBend as usize] =
+                    midi_receiver.pitch_bend() * 2.0_f32 / 12.0_f32;
+                mod_sources[ModSource::ModWheel as usize] = mod_wheel * board::DAC8164_MAX_VOLTS;
+                mod_sources[ModSource::Lfo as usize] =
+                    lfo.value() * board::DAC8164_MAX_VOLTS / 2.0_f32;
+                mod_sources[ModSource::Envelope as usize] = env.value() * board::DAC8164_MAX_VOLTS;
+                mod_sources[ModSource::Random as usize] = random_level * board::DAC8164_MAX_VOLTS;
+                mod_sources[ModSource::SlideSpeed as usize] =
+                    slide_speed.value() * board::DAC8164_MAX_VOLTS;
+
+                // the VCO channel is left unattenuated so it tracks, the pots set the levels of the other channels
+                let [vco, modosc, vcf, delay] = mod_matrix.evaluate(&mod_sources);
+                dac_outputs[0].0 = vco;
+                dac_outputs[1].0 =
+                    ui.attenuate(modosc, LevelPot::ModOsc) + ui.center_offset(LevelPot::ModOsc);
+                dac_outputs[2].0 =
+                    ui.attenuate(vcf, LevelPot::Vcf) + ui.center_offset(LevelPot::Vcf);
+                dac_outputs[3].0 =
+                    ui.attenuate(delay, LevelPot::Delay) + ui.center_offset(LevelPot::Delay);
+
+                let elapsed_cycles = board.cycle_count().wrapping_sub(start_cycles);
+                if MOD_MATRIX_CYCLE_BUDGET < elapsed_cycles {
+                    mod_matrix_overruns += 1;
+                    // the fixed routing takes over from the next update, let the player know by ear
+                    if mod_matrix_overruns == MOD_MATRIX_MAX_OVERRUNS {
+                        earcons.play(Earcon::Error);
+                    }
+                } else {
+                    mod_matrix_overruns = 0;
+                }
+            }
+
+            // derived signals may replace the usual signal on a channel
+            route_to_channel(
+                &mut dac_outputs,
+                touch_velocity * board::DAC8164_MAX_VOLTS,
+                settings.touch_velocity_channel(),
+            );
+            route_to_channel(
+                &mut dac_outputs,
+                slide_speed.value() * board::DAC8164_MAX_VOLTS,
+                settings.slide_speed_channel(),
+            );
+            add_to_channel(
+                &mut dac_outputs,
+                env.value() * settings.env_amount() * board::DAC8164_MAX_VOLTS,
+                settings.env_channel(),
+            );
+            // sequence steps with a VCF CV replace the VCF signal
+            if let Some(v_out) = seq.vcf().filter(|_| seq.is_playing()) {
+                route_to_channel(&mut dac_outputs, v_out, Some(Dac8164Channel::C));
+            }
+
+            // calibration mode replaces the pitch channels with exact whole volts for trimming the octaves
+            if calibrating {
+                let v_out = ribbon_to_calibration_volts(live_ribbon_val);
+                for output in dac_outputs.iter_mut().take(3) {
+                    output.0 = v_out;
+                }
+            }
+
+            // the settings menu plays the selected slot on the VCO channel and gate in place of the ribbon
+            let gate_out = if ui.menu_active() {
+                if live_finger_is_pressing {
+                    ui.menu_select(live_ribbon_val);
+                }
+                dac_outputs[0].0 = note_num_to_dac8164_1v_per_oct(ui.menu_note());
+                live_finger_is_pressing
+            } else {
+                gate_out
+            };
+
+            // earcons are heard over everything else while they play
+            earcons.tick();
+            let gate_out = if earcons.is_playing() {
+                dac_outputs[0].0 = note_num_to_dac8164_1v_per_oct(earcons.note());
+                earcons.gate()
+            } else {
+                gate_out
+            };
+
+            // the ribbon performance is sent out over MIDI, apart from tapping around the settings menu or calibrating
+            let midi_out_bytes = midi_out.tick(&RibbonPerformance {
+                pitch: vco_ribbon_contrib * 12.0_f32 + MIDI_OUT_TRANSPOSE_SEMITONES,
+                gate: latch.gate(finger_is_pressing) && !ui.menu_active() && !calibrating,
+                velocity: touch_velocity,
+                slide_speed: slide_speed.value(),
+                modosc_level: modosc_ribbon_contrib / MAIN_RIBBON_MAX_VOUT,
+                vcf_level: vcf_ribbon_contrib / MAIN_RIBBON_MAX_VOUT,
+            });
+            board.serial_write_all(&midi_out_bytes);
+
             // set the analog outputs
-            board.dac8164_set_vout(final_vco_ribbon, Dac8164Channel::A);
-            board.dac8164_set_vout(final_modosc_ribbon, Dac8164Channel::B);
-            board.dac8164_set_vout(final_vcf_ribbon, Dac8164Channel::C);
-            board.dac8164_set_vout(final_delay_ribbon, Dac8164Channel::D);
+            for &(v_out, channel) in dac_outputs.iter() {
+                board.dac8164_set_vout(v_out, channel);
+            }
 
-            // set the gate high with either the ribbon or MIDI signal
-            board.set_gate(ribbon.finger_is_pressing() | midi_receiver.gate());
+            board.set_gate(gate_out);
         }
     }
 }
@@ -146,7 +762,140 @@ fn ribbon_to_dac8164_1v_per_oct(ribb: f32) -> f32 {
     ribb * MAIN_RIBBON_MAX_VOUT
 }
 
+/// `ribbon_to_1v_per_oct_note_span(r, n)` is the ribbon value in `[0.0, 1.0]` scaled to 1 volt per octave so that
+/// the ribbon is divided into `n` evenly spaced notes
+fn ribbon_to_1v_per_oct_note_span(ribb: f32, num_notes: u8) -> f32 {
+    ribb * (num_notes as f32 / 12.0_f32 + RIBBON_FUDGE_FACTOR)
+}
+
+/// `ribbon_to_bend_1v_per_oct(d, r)` is the ribbon distance `d` from the first touch scaled to a 1 volt per octave bend
+/// with a range of `r` semitones
+///
+/// The bend is clamped to `[-r, +r]`, the full range is reached after sliding half the length of the ribbon.
+fn ribbon_to_bend_1v_per_oct(dist: f32, range_semitones: f32) -> f32 {
+    let semitones = dist * 2.0_f32 * range_semitones;
+    semitones.clamp(-range_semitones, range_semitones) / 12.0_f32
+}
+
+/// `ribbon_to_calibration_volts(r)` is the ribbon value in `[0.0, 1.0]` divided into equal zones of whole volts, from
+/// zero up to `CALIBRATION_MAX_VOLTS`
+fn ribbon_to_calibration_volts(ribb: f32) -> f32 {
+    libm::floorf(ribb * (CALIBRATION_MAX_VOLTS + 1.0_f32)).min(CALIBRATION_MAX_VOLTS)
+}
+
 /// `note_num_to_dac8164_1v_per_oct(n)` is the note number `n` scaled to 1volt/octave
 fn note_num_to_dac8164_1v_per_oct(note_num: u8) -> f32 {
     note_num as f32 / 12.0_f32
 }
+
+/// `apply_settings(s, u, gp, rf, a, cs, sq)` applies the user settings `s` to the UI `u`, gate processor `gp`, release
+/// freeze `rf`, arpeggiator `a`, chord strummer `cs`, and step sequencer `sq`
+fn apply_settings(
+    settings: &Settings,
+    ui: &mut UiState,
+    gate_processor: &mut GateProcessor,
+    release_freeze: &mut ReleaseFreeze,
+    arp: &mut Arpeggiator,
+    strum: &mut ChordStrum,
+    seq: &mut Sequencer,
+) {
+    ui.set_bipolar(LevelPot::ModOsc, settings.modosc_pot_bipolar());
+    ui.set_bipolar(LevelPot::Vcf, settings.vcf_pot_bipolar());
+    ui.set_bipolar(LevelPot::Delay, settings.delay_pot_bipolar());
+    ui.set_bipolar_center_volts(settings.bipolar_center_volts());
+    ui.set_pot_smoothing(settings.pot_smoothing());
+    ui.set_takeover_mode(settings.takeover_mode());
+    ui.set_vco_pot_mode(settings.vco_pot_mode());
+    ui.set_bender_enabled(settings.bender_enabled());
+    for &control in &[
+        LevelPot::Vco,
+        LevelPot::ModOsc,
+        LevelPot::Vcf,
+        LevelPot::Delay,
+    ] {
+        ui.set_response(control, settings.pot_response(control));
+    }
+
+    gate_processor.set_mode(settings.gate_mode());
+    gate_processor.set_retrigger_ms(settings.gate_retrigger_ms());
+    gate_processor.set_trigger_ms(settings.gate_trigger_ms());
+    gate_processor.set_min_gate_ms(settings.min_gate_ms());
+
+    let freeze_samples = settings.release_freeze_ms() * FAST_RIBBON_SAMPLE_RATE / 1_000;
+    release_freeze.set_lookback(freeze_samples as usize);
+
+    arp.set_order(settings.arp_order());
+    arp.set_num_octaves(settings.arp_num_octaves());
+    arp.set_gate_length(settings.arp_gate_length());
+    arp.set_tempo_bpm(settings.arp_tempo_bpm());
+    arp.set_clock_source(settings.arp_clock_source());
+
+    // the strum shares the arpeggiator tempo
+    strum.set_root(settings.root());
+    strum.set_scale(settings.scale());
+    strum.set_chord_size(settings.chord_size());
+    strum.set_tempo_bpm(settings.arp_tempo_bpm());
+
+    // the sequencer shares the arpeggiator clock
+    seq.set_mode(settings.seq_mode());
+    seq.set_len(settings.seq_len());
+    seq.set_tempo_bpm(settings.arp_tempo_bpm());
+    seq.set_clock_source(settings.arp_clock_source());
+}
+
+/// `apply_modulation_settings(s, l, e)` applies the user settings `s` to the LFO `l` and envelope `e`
+fn apply_modulation_settings(settings: &Settings, lfo: &mut Lfo, env: &mut Envelope) {
+    lfo.set_waveform(settings.lfo_waveform());
+    lfo.set_rate_hz(settings.lfo_rate_hz());
+    lfo.set_sync(settings.lfo_sync());
+    lfo.set_delay_ms(settings.lfo_delay_ms());
+    lfo.set_key_reset(settings.lfo_key_reset());
+
+    env.set_mode(settings.env_mode());
+    env.set_attack_ms(settings.env_attack_ms());
+    env.set_decay_ms(settings.env_decay_ms());
+    env.set_sustain(settings.env_sustain());
+    env.set_release_ms(settings.env_release_ms());
+}
+
+/// `apply_midi_out_settings(s, mo)` applies the user settings `s` to the MIDI output `mo`
+fn apply_midi_out_settings(settings: &Settings, midi_out: &mut MidiOutput) {
+    midi_out.set_mode(settings.midi_out_mode());
+    midi_out.set_bend_range(settings.midi_out_bend_range());
+}
+
+/// `route_to_channel(os, v, c)` replaces the voltage for channel `c` in the outputs `os` with `v`, if `c` is some channel
+fn route_to_channel(
+    outputs: &mut [(f32, Dac8164Channel)],
+    v_out: f32,
+    channel: Option<Dac8164Channel>,
+) {
+    if let Some(channel) = channel {
+        for output in outputs.iter_mut().filter(|(_, c)| *c == channel) {
+            output.0 = v_out;
+        }
+    }
+}
+
+/// `add_to_channel(os, v, c)` adds the voltage `v` to the voltage for channel `c` in the outputs `os`, if `c` is some
+/// channel
+fn add_to_channel(
+    outputs: &mut [(f32, Dac8164Channel)],
+    v_out: f32,
+    channel: Option<Dac8164Channel>,
+) {
+    if let Some(channel) = channel {
+        for output in outputs.iter_mut().filter(|(_, c)| *c == channel) {
+            output.0 += v_out;
+        }
+    }
+}
+
+/// `save_earcon(s)` is the earcon confirming a save, `s` is true iff the save succeeded
+fn save_earcon(saved: bool) -> Earcon {
+    if saved {
+        Earcon::Saved
+    } else {
+        Earcon::Error
+    }
+}
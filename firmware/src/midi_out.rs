@@ -0,0 +1,506 @@
+use heapless::Vec;
+
+use crate::timing::ms_to_ticks;
+
+/// The MIDI output of ribbon performances is represented here
+///
+/// The ribbon pitch is sent as the nearest note plus a 14 bit pitch bend carrying the smooth offset from that note, so
+/// a receiver with the same bend range reproduces the pitch exactly. Notes follow the gate, and the MODOSC and VCF
+/// ribbon levels can optionally be sent as control changes. Only the messages for things which changed are sent.
+///
+/// In MPE mode the output is an MPE lower zone instead. Each touch plays one note on its own member channel and holds
+/// it for the whole touch, while the bend on that channel carries the rest of the pitch.
+pub struct MidiOutput {
+    channel: u8,
+    mode: MidiOutMode,
+    bend_range_semitones: f32,
+    level_interval_ticks: u32,
+
+    playing: Option<PlayingNote>,
+    prev_bend: Option<u16>,
+    prev_levels: [Option<u8>; 2],
+    prev_timbre: u8,
+    ticks_since_levels: u32,
+
+    mpe_configured: bool,
+    config_interval_ticks: u32,
+    ticks_since_config: u32,
+    next_member_channel: u8,
+}
+
+/// What the MIDI output sends is represented here
+///
+/// * `Off` - nothing is sent
+///
+/// * `Notes` - notes and pitch bend
+///
+/// * `NotesAndLevels` - notes and pitch bend, plus control changes for the MODOSC and VCF ribbon levels
+///
+/// * `Mpe` - an MPE lower zone, each touch is a note on its own member channel with its own pitch bend, the slide
+///   speed is sent as CC74 and the touch velocity as channel pressure
+#[derive(Clone, Copy, PartialEq)]
+pub enum MidiOutMode {
+    Off,
+    Notes,
+    NotesAndLevels,
+    Mpe,
+}
+
+/// The state of a ribbon performance at one moment is represented here
+///
+/// * `pitch` - the pitch in semitones above the lowest MIDI note
+///
+/// * `gate` - true iff a note should be sounding
+///
+/// * `velocity` - the touch velocity in `[0.0, 1.0]`
+///
+/// * `slide_speed` - the slide speed in `[0.0, 1.0]`
+///
+/// * `modosc_level` - the MODOSC ribbon level in `[0.0, 1.0]`
+///
+/// * `vcf_level` - the VCF ribbon level in `[0.0, 1.0]`
+pub struct RibbonPerformance {
+    pub pitch: f32,
+    pub gate: bool,
+    pub velocity: f32,
+    pub slide_speed: f32,
+    pub modosc_level: f32,
+    pub vcf_level: f32,
+}
+
+/// A note sounding at the receiver, along with the channel and mode it was started in
+#[derive(Clone, Copy)]
+struct PlayingNote {
+    channel: u8,
+    note: u8,
+    mode: MidiOutMode,
+}
+
+impl MidiOutput {
+    /// `MidiOutput::new(r, c)` is a new MIDI output sending on channel `c` which will be ticked at `r` hertz, channels
+    /// are zero indexed
+    ///
+    /// The MPE mode ignores the channel, a lower zone is always managed from the first channel.
+    pub fn new(tick_rate_hz: u32, channel: u8) -> Self {
+        Self {
+            channel,
+            mode: MidiOutMode::Off,
+            bend_range_semitones: 2.0_f32,
+            level_interval_ticks: (tick_rate_hz / LEVEL_RATE_HZ).max(1),
+
+            playing: None,
+            prev_bend: None,
+            prev_levels: [None; 2],
+            prev_timbre: 0,
+            ticks_since_levels: 0,
+
+            mpe_configured: false,
+            config_interval_ticks: ms_to_ticks(MPE_CONFIG_INTERVAL_MS, tick_rate_hz),
+            ticks_since_config: 0,
+            next_member_channel: MPE_FIRST_MEMBER_CHANNEL,
+        }
+    }
+
+    /// `midi_out.set_mode(m)` sets what the MIDI output sends
+    pub fn set_mode(&mut self, mode: MidiOutMode) {
+        if mode != self.mode {
+            // everything is sent fresh in the new mode, and entering MPE mode configures the zone again
+            self.prev_bend = None;
+            self.prev_levels = [None; 2];
+            self.mpe_configured = false;
+        }
+        self.mode = mode;
+    }
+
+    /// `midi_out.reconfigure()` sends the MPE configuration again before the next MPE note, such as when MPE mode is
+    /// picked again for a receiver which missed it
+    pub fn reconfigure(&mut self) {
+        self.mpe_configured = false;
+    }
+
+    /// `midi_out.set_bend_range(s)` sets the pitch bend range to `s` semitones, the receiver must use the same range
+    ///
+    /// The MPE mode ignores this range, the member channels use the MPE default of `MPE_BEND_RANGE_SEMITONES`.
+    pub fn set_bend_range(&mut self, semitones: u8) {
+        self.bend_range_semitones = semitones.max(1) as f32;
+    }
+
+    /// `midi_out.tick(p)` is the bytes to send for the ribbon performance `p`
+    ///
+    /// It is required to call this function at the tick rate given when the output was created.
+    pub fn tick(&mut self, perf: &RibbonPerformance) -> Vec<u8, MIDI_OUT_MAX_BYTES> {
+        let mut bytes = Vec::new();
+        self.ticks_since_levels = self.ticks_since_levels.saturating_add(1);
+        self.ticks_since_config = self.ticks_since_config.saturating_add(1);
+
+        let mpe = self.mode == MidiOutMode::Mpe;
+        let nearest_note = libm::roundf(perf.pitch).clamp(0.0_f32, 127.0_f32) as u8;
+        let sounding = perf.gate && self.mode != MidiOutMode::Off;
+
+        // a note ends when the gate falls or the mode changes, outside of MPE mode it also ends when the ribbon moves
+        // to a new note
+        let mut note_changed = false;
+        if let Some(playing) = self
+            .playing
+            .filter(|p| !sounding || p.mode != self.mode || (!mpe && p.note != nearest_note))
+        {
+            bytes
+                .extend_from_slice(&note_off(playing.channel, playing.note))
+                .ok();
+            // the member channel is left with no timbre for the next note played on it
+            if playing.mode == MidiOutMode::Mpe && self.prev_timbre != 0 {
+                bytes
+                    .extend_from_slice(&control_change(playing.channel, CC_MPE_TIMBRE, 0))
+                    .ok();
+            }
+            self.playing = None;
+            self.prev_bend = None;
+            self.prev_timbre = 0;
+            note_changed = true;
+        }
+        if self.mode == MidiOutMode::Off {
+            return bytes;
+        }
+
+        // the zone is configured before the first MPE note, in a tick of its own, and again now and then while no note
+        // is playing for receivers which were powered on later
+        let config_due = !sounding
+            && self.playing.is_none()
+            && self.config_interval_ticks <= self.ticks_since_config;
+        if mpe && (!self.mpe_configured || config_due) {
+            if bytes.is_empty() {
+                let config = mpe_configuration(MPE_MANAGER_CHANNEL, MPE_NUM_MEMBER_CHANNELS);
+                bytes.extend_from_slice(&config).ok();
+                self.mpe_configured = true;
+                self.ticks_since_config = 0;
+            }
+            return bytes;
+        }
+
+        let bend_range = if mpe {
+            MPE_BEND_RANGE_SEMITONES as f32
+        } else {
+            self.bend_range_semitones
+        };
+
+        match self.playing {
+            Some(playing) => {
+                let bend = bend_to_14_bit(perf.pitch - playing.note as f32, bend_range);
+                if self.prev_bend != Some(bend) {
+                    bytes
+                        .extend_from_slice(&pitch_bend(playing.channel, bend))
+                        .ok();
+                    self.prev_bend = Some(bend);
+                }
+            }
+            None if sounding => {
+                // the bend, and in MPE mode the pressure, are set before the note starts so it starts right
+                let channel = if mpe {
+                    self.next_member_channel
+                } else {
+                    self.channel
+                };
+                let bend = bend_to_14_bit(perf.pitch - nearest_note as f32, bend_range);
+                let velocity = 1 + (perf.velocity.clamp(0.0_f32, 1.0_f32) * 126.0_f32) as u8;
+
+                let mut start: Vec<u8, MIDI_OUT_MAX_BYTES> = Vec::new();
+                start.extend_from_slice(&pitch_bend(channel, bend)).ok();
+                if mpe {
+                    start
+                        .extend_from_slice(&channel_pressure(channel, velocity))
+                        .ok();
+                }
+                start
+                    .extend_from_slice(&note_on(channel, nearest_note, velocity))
+                    .ok();
+
+                // a note which doesn't fit after the messages already in this tick starts in the next one
+                if bytes.len() + start.len() <= MIDI_OUT_MAX_BYTES {
+                    bytes.extend_from_slice(&start).ok();
+                    self.prev_bend = Some(bend);
+                    self.playing = Some(PlayingNote {
+                        channel,
+                        note: nearest_note,
+                        mode: self.mode,
+                    });
+                    if mpe {
+                        self.next_member_channel = next_member_channel(channel);
+                    }
+                }
+                note_changed = true;
+            }
+            None => (),
+        }
+
+        // the levels and timbre change continuously, so they are sent at a lower rate and wait out the ticks where the
+        // note changes, keeping each tick short enough to send before the next one
+        if self.level_interval_ticks <= self.ticks_since_levels && !note_changed {
+            self.ticks_since_levels = 0;
+            match (self.mode, self.playing) {
+                (MidiOutMode::NotesAndLevels, _) => {
+                    let levels = [perf.modosc_level, perf.vcf_level];
+                    let controls = [CC_MODOSC_LEVEL, CC_VCF_LEVEL];
+                    for ((&level, &control), prev) in levels
+                        .iter()
+                        .zip(controls.iter())
+                        .zip(self.prev_levels.iter_mut())
+                    {
+                        let value = (level.clamp(0.0_f32, 1.0_f32) * 127.0_f32) as u8;
+                        if *prev != Some(value) {
+                            bytes
+                                .extend_from_slice(&control_change(self.channel, control, value))
+                                .ok();
+                            *prev = Some(value);
+                        }
+                    }
+                }
+                (MidiOutMode::Mpe, Some(playing)) => {
+                    let timbre = (perf.slide_speed.clamp(0.0_f32, 1.0_f32) * 127.0_f32) as u8;
+                    if self.prev_timbre != timbre {
+                        bytes
+                            .extend_from_slice(&control_change(
+                                playing.channel,
+                                CC_MPE_TIMBRE,
+                                timbre,
+                            ))
+                            .ok();
+                        self.prev_timbre = timbre;
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        bytes
+    }
+}
+
+/// The most bytes sent by a single tick: a note-off, a pitch bend, and a note-on, or the MPE configuration, or a pitch
+/// bend and two control changes
+///
+/// At 31250 baud these take under 3 milliseconds to send, so a 300 hertz tick never waits on the one before.
+pub const MIDI_OUT_MAX_BYTES: usize = 9;
+
+/// The control change number the MODOSC level is sent on, sound controller 7
+pub const CC_MODOSC_LEVEL: u8 = 76;
+
+/// The control change number the VCF level is sent on, sound controller 5 which is usually brightness
+pub const CC_VCF_LEVEL: u8 = 74;
+
+/// The control change number MPE uses for the third dimension of expression, here the slide speed
+pub const CC_MPE_TIMBRE: u8 = 74;
+
+/// The pitch bend range of the MPE member channels, the MPE default which receivers reset to when a zone is configured
+pub const MPE_BEND_RANGE_SEMITONES: u8 = 48;
+
+/// The manager channel of an MPE lower zone is the first channel, the member channels are all of the rest
+const MPE_MANAGER_CHANNEL: u8 = 0;
+const MPE_FIRST_MEMBER_CHANNEL: u8 = 1;
+const MPE_NUM_MEMBER_CHANNELS: u8 = 15;
+
+/// The rate the levels are sent at, when they change
+const LEVEL_RATE_HZ: u32 = 50;
+
+/// How often the MPE configuration is sent again while no note is playing
+const MPE_CONFIG_INTERVAL_MS: u32 = 2_000;
+
+/// `note_on(c, n, v)` is the note-on message for note `n` with velocity `v` on channel `c`
+pub fn note_on(channel: u8, note: u8, velocity: u8) -> [u8; 3] {
+    [0x90 | (channel & 0x0F), note & 0x7F, velocity & 0x7F]
+}
+
+/// `note_off(c, n)` is the note-off message for note `n` on channel `c`
+pub fn note_off(channel: u8, note: u8) -> [u8; 3] {
+    [0x80 | (channel & 0x0F), note & 0x7F, 0]
+}
+
+/// `control_change(c, n, v)` is the control change message setting control `n` to value `v` on channel `c`
+pub fn control_change(channel: u8, control: u8, value: u8) -> [u8; 3] {
+    [0xB0 | (channel & 0x0F), control & 0x7F, value & 0x7F]
+}
+
+/// `channel_pressure(c, p)` is the channel pressure message with pressure `p` on channel `c`
+pub fn channel_pressure(channel: u8, pressure: u8) -> [u8; 2] {
+    [0xD0 | (channel & 0x0F), pressure & 0x7F]
+}
+
+/// `pitch_bend(c, b)` is the pitch bend message for the 14 bit bend `b` on channel `c`, the center is 8192
+pub fn pitch_bend(channel: u8, bend: u16) -> [u8; 3] {
+    [
+        0xE0 | (channel & 0x0F),
+        (bend & 0x7F) as u8,
+        ((bend >> 7) & 0x7F) as u8,
+    ]
+}
+
+/// `mpe_configuration(c, n)` is the MPE configuration message giving the zone managed from channel `c` a total of `n`
+/// member channels, a manager channel of 0 is the lower zone
+///
+/// The message sets registered parameter 6 with a data entry.
+pub fn mpe_configuration(manager_channel: u8, num_member_channels: u8) -> [u8; 9] {
+    let status = 0xB0 | (manager_channel & 0x0F);
+    [
+        status,
+        CC_RPN_MSB,
+        0,
+        status,
+        CC_RPN_LSB,
+        RPN_MPE_CONFIGURATION,
+        status,
+        CC_DATA_ENTRY_MSB,
+        num_member_channels & 0x7F,
+    ]
+}
+
+/// `bend_to_14_bit(s, r)` is the bend of `s` semitones as a 14 bit pitch bend with a range of `r` semitones
+///
+/// Bends past the range are clamped to the ends of the range.
+pub fn bend_to_14_bit(semitones: f32, range_semitones: f32) -> u16 {
+    let bend = BEND_CENTER as f32 + semitones / range_semitones * BEND_CENTER as f32;
+    libm::roundf(bend).clamp(0.0_f32, BEND_MAX as f32) as u16
+}
+
+/// `next_member_channel(c)` is the MPE member channel after channel `c`, wrapping around to the first one
+///
+/// Rotating through the member channels leaves the release of each note undisturbed by the bend of the next.
+fn next_member_channel(channel: u8) -> u8 {
+    if MPE_FIRST_MEMBER_CHANNEL + MPE_NUM_MEMBER_CHANNELS - 1 <= channel {
+        MPE_FIRST_MEMBER_CHANNEL
+    } else {
+        channel + 1
+    }
+}
+
+/// The 14 bit pitch bend with no bend
+const BEND_CENTER: u16 = 8192;
+
+/// The largest 14 bit pitch bend
+const BEND_MAX: u16 = (1 << 14) - 1;
+
+/// The control change numbers for picking a registered parameter and entering its value
+const CC_RPN_MSB: u8 = 101;
+const CC_RPN_LSB: u8 = 100;
+const CC_DATA_ENTRY_MSB: u8 = 6;
+
+/// The registered parameter number of the MPE configuration message
+const RPN_MPE_CONFIGURATION: u8 = 6;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `performance(p, g)` is a performance at pitch `p` with the gate `g` at full velocity
+    fn performance(pitch: f32, gate: bool) -> RibbonPerformance {
+        RibbonPerformance {
+            pitch,
+            gate,
+            velocity: 1.0_f32,
+            slide_speed: 0.0_f32,
+            modosc_level: 0.0_f32,
+            vcf_level: 0.0_f32,
+        }
+    }
+
+    #[test]
+    fn bends_are_14_bit() {
+        assert_eq!(bend_to_14_bit(0.0_f32, 2.0_f32), 8192);
+        assert_eq!(bend_to_14_bit(1.0_f32, 2.0_f32), 12288);
+        assert_eq!(bend_to_14_bit(-1.0_f32, 2.0_f32), 4096);
+        assert_eq!(bend_to_14_bit(-2.0_f32, 2.0_f32), 0);
+        assert_eq!(bend_to_14_bit(2.0_f32, 2.0_f32), 16383);
+        assert_eq!(bend_to_14_bit(-5.0_f32, 2.0_f32), 0);
+        assert_eq!(bend_to_14_bit(5.0_f32, 2.0_f32), 16383);
+    }
+
+    #[test]
+    fn message_bytes() {
+        assert_eq!(pitch_bend(0, 0), [0xE0, 0x00, 0x00]);
+        assert_eq!(pitch_bend(0, 8192), [0xE0, 0x00, 0x40]);
+        assert_eq!(pitch_bend(3, 16383), [0xE3, 0x7F, 0x7F]);
+        assert_eq!(note_on(2, 60, 100), [0x92, 60, 100]);
+        assert_eq!(note_off(2, 60), [0x82, 60, 0]);
+        assert_eq!(control_change(15, 74, 127), [0xBF, 74, 127]);
+        assert_eq!(channel_pressure(1, 64), [0xD1, 64]);
+    }
+
+    #[test]
+    fn mpe_configuration_bytes() {
+        // registered parameter 6 set to 15 member channels, on the manager channel of the lower zone
+        assert_eq!(
+            mpe_configuration(0, 15),
+            [0xB0, 101, 0, 0xB0, 100, 6, 0xB0, 6, 15]
+        );
+        assert_eq!(
+            mpe_configuration(15, 4),
+            [0xBF, 101, 0, 0xBF, 100, 6, 0xBF, 6, 4]
+        );
+    }
+
+    #[test]
+    fn mpe_is_configured_before_the_first_note() {
+        let mut midi_out = MidiOutput::new(300, 0);
+        midi_out.set_mode(MidiOutMode::Mpe);
+        let config = mpe_configuration(MPE_MANAGER_CHANNEL, MPE_NUM_MEMBER_CHANNELS);
+
+        assert_eq!(midi_out.tick(&performance(60.0_f32, true))[..], config);
+        // bend, pressure, and note-on on the first member channel
+        let bytes = midi_out.tick(&performance(60.0_f32, true));
+        assert_eq!(bytes[..], [0xE1, 0x00, 0x40, 0xD1, 127, 0x91, 60, 127]);
+    }
+
+    #[test]
+    fn mpe_configuration_is_sent_again() {
+        let mut midi_out = MidiOutput::new(300, 0);
+        midi_out.set_mode(MidiOutMode::Mpe);
+        let config = mpe_configuration(MPE_MANAGER_CHANNEL, MPE_NUM_MEMBER_CHANNELS);
+        let interval_ticks = MPE_CONFIG_INTERVAL_MS * 300 / 1_000;
+
+        // picking MPE mode again sends it right away
+        assert_eq!(midi_out.tick(&performance(60.0_f32, false))[..], config);
+        midi_out.reconfigure();
+        assert_eq!(midi_out.tick(&performance(60.0_f32, false))[..], config);
+
+        // and it is sent now and then while no note is playing
+        for _ in 1..interval_ticks {
+            assert!(midi_out.tick(&performance(60.0_f32, false)).is_empty());
+        }
+        assert_eq!(midi_out.tick(&performance(60.0_f32, false))[..], config);
+
+        // but never in the middle of a note
+        for _ in 0..2 * interval_ticks {
+            let bytes = midi_out.tick(&performance(60.0_f32, true));
+            assert!(!bytes.starts_with(&config[..3]));
+        }
+    }
+
+    #[test]
+    fn member_channels_rotate() {
+        assert_eq!(next_member_channel(1), 2);
+        assert_eq!(next_member_channel(14), 15);
+        assert_eq!(next_member_channel(15), 1);
+    }
+
+    #[test]
+    fn notes_follow_the_gate_and_pitch() {
+        let mut midi_out = MidiOutput::new(300, 0);
+        midi_out.set_mode(MidiOutMode::Notes);
+
+        // a quarter semitone above middle C is a bend of 1024 above the center with the default 2 semitone range
+        let bytes = midi_out.tick(&performance(60.25_f32, true));
+        assert_eq!(bytes[..], [0xE0, 0x00, 0x48, 0x90, 60, 127]);
+        assert!(midi_out.tick(&performance(60.25_f32, true)).is_empty());
+
+        // moving to a new note ends the old one and starts the new one with its bend reset
+        let bytes = midi_out.tick(&performance(62.0_f32, true));
+        assert_eq!(bytes[..], [0x80, 60, 0, 0xE0, 0x00, 0x40, 0x90, 62, 127]);
+
+        let bytes = midi_out.tick(&performance(62.0_f32, false));
+        assert_eq!(bytes[..], [0x80, 62, 0]);
+    }
+
+    #[test]
+    fn off_sends_nothing() {
+        let mut midi_out = MidiOutput::new(300, 0);
+        assert!(midi_out.tick(&performance(60.0_f32, true)).is_empty());
+    }
+}
@@ -0,0 +1,111 @@
+/// A MIDI message parser for the messages not handled by the mono MIDI receiver is represented here
+///
+/// The mono MIDI receiver only keeps track of the current note, gate, and pitch bend. This parser listens to the same
+/// stream of bytes and picks out the other messages that the firmware cares about, such as control changes, the clock,
+/// and the individual note-on and note-off messages used by the arpeggiator.
+pub struct MidiParser {
+    channel: u8,
+    running_status: Option<u8>,
+    data: [u8; 2],
+    num_data_bytes: usize,
+}
+
+/// The MIDI messages which the parser can recognize are represented here
+#[derive(Clone, Copy)]
+pub enum MidiMessage {
+    NoteOn { note: u8 },
+    NoteOff { note: u8 },
+    ControlChange { control: u8, value: u8 },
+    Clock,
+    Start,
+    Continue,
+    Stop,
+}
+
+/// The mod wheel control change number
+pub const CC_MOD_WHEEL: u8 = 1;
+
+/// The sustain pedal control change number
+pub const CC_SUSTAIN_PEDAL: u8 = 64;
+
+impl MidiParser {
+    /// `MidiParser::new(c)` is a new MIDI parser which listens to channel `c`, channels are zero indexed
+    pub fn new(channel: u8) -> Self {
+        Self {
+            channel,
+            running_status: None,
+            data: [0; 2],
+            num_data_bytes: 0,
+        }
+    }
+
+    /// `parser.parse(b)` is the optional MIDI message completed by the byte `b`
+    ///
+    /// Bytes must be fed to the parser in the order they are received. Running status is supported, and system
+    /// real-time bytes may be interleaved anywhere in the stream.
+    pub fn parse(&mut self, byte: u8) -> Option<MidiMessage> {
+        if is_system_real_time(byte) {
+            // real-time bytes don't disturb running status
+            return match byte {
+                0xF8 => Some(MidiMessage::Clock),
+                0xFA => Some(MidiMessage::Start),
+                0xFB => Some(MidiMessage::Continue),
+                0xFC => Some(MidiMessage::Stop),
+                _ => None,
+            };
+        }
+
+        if is_status_byte(byte) {
+            // system common messages cancel running status, we don't care about any of them
+            self.running_status = if byte < 0xF0 { Some(byte) } else { None };
+            self.num_data_bytes = 0;
+            return None;
+        }
+
+        let status = self.running_status?;
+
+        self.data[self.num_data_bytes] = byte;
+        self.num_data_bytes += 1;
+
+        if self.num_data_bytes < num_data_bytes_for_status(status) {
+            return None;
+        }
+        // the message is complete, further data bytes use the running status
+        self.num_data_bytes = 0;
+
+        if status & 0x0F != self.channel {
+            return None;
+        }
+
+        match status & 0xF0 {
+            0x80 => Some(MidiMessage::NoteOff { note: self.data[0] }),
+            // a note-on with zero velocity is really a note-off
+            0x90 if self.data[1] == 0 => Some(MidiMessage::NoteOff { note: self.data[0] }),
+            0x90 => Some(MidiMessage::NoteOn { note: self.data[0] }),
+            0xB0 => Some(MidiMessage::ControlChange {
+                control: self.data[0],
+                value: self.data[1],
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// `is_status_byte(b)` is true iff byte `b` is a MIDI status byte
+fn is_status_byte(byte: u8) -> bool {
+    byte & 0x80 != 0
+}
+
+/// `is_system_real_time(b)` is true iff byte `b` is a MIDI system real-time message
+fn is_system_real_time(byte: u8) -> bool {
+    0xF8 <= byte
+}
+
+/// `num_data_bytes_for_status(s)` is the number of data bytes which follow the channel status byte `s`
+fn num_data_bytes_for_status(status: u8) -> usize {
+    match status & 0xF0 {
+        // program change and channel pressure have a single data byte
+        0xC0 | 0xD0 => 1,
+        _ => 2,
+    }
+}
@@ -0,0 +1,247 @@
+/// A modulation routing matrix from the modulation sources to the four DAC channels is represented here
+///
+/// Each channel is the sum of every source scaled by its signed amount for that channel. The sources are all given in
+/// volts, so a pitch source routed with an amount of `1.0` still tracks at 1 volt per octave.
+pub struct ModMatrix {
+    amounts: [[f32; NUM_MOD_SOURCES]; NUM_MOD_DESTINATIONS],
+}
+
+/// The modulation sources are represented here, the discriminants index the source values given to the matrix
+#[derive(Clone, Copy)]
+pub enum ModSource {
+    RibbonPosition = 0,
+    QuantizedPitch,
+    MidiNote,
+    Velocity,
+    PitchBend,
+    ModWheel,
+    Lfo,
+    Envelope,
+    Random,
+    SlideSpeed,
+}
+
+/// The routings which can be selected are represented here
+///
+/// * `Fixed` - the usual routing, each channel is the attenuated ribbon plus MIDI for the VCO, MODOSC, VCF and Delay
+///
+/// * `Lead` - a preset with a quantized lead on the VCO and expressive ribbon control of the VCF
+///
+/// * `Motion` - a preset with the LFO, envelope, and random modulation spread over the channels
+///
+/// * `User` - the preset edited over MIDI, it can be saved to flash
+#[derive(Clone, Copy)]
+pub enum ModRouting {
+    Fixed,
+    Lead,
+    Motion,
+    User,
+}
+
+impl ModMatrix {
+    /// `ModMatrix::new()` is a new matrix with nothing routed anywhere
+    pub fn new() -> Self {
+        Self {
+            amounts: [[0.0_f32; NUM_MOD_SOURCES]; NUM_MOD_DESTINATIONS],
+        }
+    }
+
+    /// `ModMatrix::from_routes(rs)` is a new matrix with each of the routes `rs` given as a destination channel
+    /// index, source, and amount
+    pub fn from_routes(routes: &[(usize, ModSource, f32)]) -> Self {
+        let mut matrix = Self::new();
+        for &(dest, source, amount) in routes {
+            matrix.amounts[dest][source as usize] = amount;
+        }
+        matrix
+    }
+
+    /// `ModMatrix::lead()` is the lead preset
+    pub fn lead() -> Self {
+        Self::from_routes(&[
+            (0, ModSource::QuantizedPitch, 1.0_f32),
+            (0, ModSource::MidiNote, 1.0_f32),
+            (0, ModSource::PitchBend, 1.0_f32),
+            (1, ModSource::RibbonPosition, 1.0_f32),
+            (1, ModSource::Lfo, 0.1_f32),
+            (2, ModSource::RibbonPosition, 1.0_f32),
+            (2, ModSource::Velocity, 0.3_f32),
+            (2, ModSource::Envelope, 0.5_f32),
+            (3, ModSource::SlideSpeed, 1.0_f32),
+        ])
+    }
+
+    /// `ModMatrix::motion()` is the motion preset
+    pub fn motion() -> Self {
+        Self::from_routes(&[
+            (0, ModSource::QuantizedPitch, 1.0_f32),
+            (0, ModSource::MidiNote, 1.0_f32),
+            (0, ModSource::PitchBend, 1.0_f32),
+            (1, ModSource::Lfo, 1.0_f32),
+            (1, ModSource::ModWheel, 0.5_f32),
+            (2, ModSource::Envelope, 1.0_f32),
+            (2, ModSource::Lfo, -0.2_f32),
+            (3, ModSource::Random, 1.0_f32),
+        ])
+    }
+
+    /// `matrix.set_amount(c, a)` sets the amount for the cell `c` to `a`, clamped to `[-1.0, 1.0]`
+    ///
+    /// The cells are numbered across the sources for the first channel, then the second channel, and so on.
+    pub fn set_amount(&mut self, cell: usize, amount: f32) {
+        let cell = cell.min(NUM_MOD_CELLS - 1);
+        self.amounts[cell / NUM_MOD_SOURCES][cell % NUM_MOD_SOURCES] =
+            amount.clamp(-1.0_f32, 1.0_f32);
+    }
+
+    /// `matrix.evaluate(ss)` is the voltage for each channel given the source values `ss` in volts
+    pub fn evaluate(&self, sources: &[f32; NUM_MOD_SOURCES]) -> [f32; NUM_MOD_DESTINATIONS] {
+        let mut outputs = [0.0_f32; NUM_MOD_DESTINATIONS];
+        for (output, amounts) in outputs.iter_mut().zip(self.amounts.iter()) {
+            for (&amount, &source) in amounts.iter().zip(sources.iter()) {
+                // most cells are empty, skipping them keeps the evaluation well inside the cycle budget
+                if amount != 0.0_f32 {
+                    *output += amount * source;
+                }
+            }
+        }
+        outputs
+    }
+
+    /// `matrix.to_words()` is the matrix packed into words for storing in flash
+    pub fn to_words(&self) -> [u64; MOD_MATRIX_NUM_WORDS] {
+        let mut words = [0; MOD_MATRIX_NUM_WORDS];
+        words[0] = STORAGE_MAGIC as u64;
+        for cell in 0..NUM_MOD_CELLS {
+            let amount = self.amounts[cell / NUM_MOD_SOURCES][cell % NUM_MOD_SOURCES];
+            let byte = libm::roundf(amount * 127.0_f32) as i8 as u8;
+            words[1 + cell / 8] |= (byte as u64) << (8 * (cell % 8));
+        }
+        words
+    }
+
+    /// `matrix.load_words(ws)` replaces the matrix with the one packed into the words `ws`
+    ///
+    /// # Returns:
+    ///
+    /// * true iff the words held a stored matrix, erased flash or garbage is ignored
+    pub fn load_words(&mut self, words: &[u64; MOD_MATRIX_NUM_WORDS]) -> bool {
+        if words[0] != STORAGE_MAGIC as u64
+            || words[1..]
+                .iter()
+                .any(|word| word.to_le_bytes().contains(&UNUSED_AMOUNT_BYTE))
+        {
+            return false;
+        }
+        for cell in 0..NUM_MOD_CELLS {
+            let byte = (words[1 + cell / 8] >> (8 * (cell % 8))) as u8;
+            self.set_amount(cell, byte as i8 as f32 / 127.0_f32);
+        }
+        true
+    }
+}
+
+/// The number of modulation sources
+pub const NUM_MOD_SOURCES: usize = 10;
+
+/// The number of modulation destinations, one for each DAC channel
+pub const NUM_MOD_DESTINATIONS: usize = 4;
+
+/// The number of cells in the matrix, one for each source and destination pair
+pub const NUM_MOD_CELLS: usize = NUM_MOD_SOURCES * NUM_MOD_DESTINATIONS;
+
+/// The number of 64 bit words used to store a matrix, a header followed by one byte per cell
+pub const MOD_MATRIX_NUM_WORDS: usize = 1 + NUM_MOD_CELLS.div_ceil(8);
+
+/// Marks a word in flash as the header of a stored matrix
+const STORAGE_MAGIC: u32 = 0x4D4F_4431;
+
+/// The amounts are stored as bytes in `[-127, 127]`, so the byte for `-128` only turns up in damaged flash
+const UNUSED_AMOUNT_BYTE: u8 = 0x80;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `sources(ss)` is the source values with each of the sources `ss` given a voltage and the rest at zero
+    fn sources(values: &[(ModSource, f32)]) -> [f32; NUM_MOD_SOURCES] {
+        let mut sources = [0.0_f32; NUM_MOD_SOURCES];
+        for &(source, value) in values.iter() {
+            sources[source as usize] = value;
+        }
+        sources
+    }
+
+    #[test]
+    fn routed_cells_are_summed() {
+        let matrix = ModMatrix::from_routes(&[
+            (0, ModSource::MidiNote, 1.0_f32),
+            (0, ModSource::PitchBend, 1.0_f32),
+            (2, ModSource::Lfo, -0.5_f32),
+            (2, ModSource::Envelope, 0.25_f32),
+        ]);
+        let outputs = matrix.evaluate(&sources(&[
+            (ModSource::MidiNote, 2.0_f32),
+            (ModSource::PitchBend, 0.25_f32),
+            (ModSource::Lfo, 1.0_f32),
+            (ModSource::Envelope, 4.0_f32),
+            (ModSource::Random, 3.0_f32),
+        ]));
+        assert_eq!(outputs, [2.25, 0.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn amounts_are_set_by_cell() {
+        let mut matrix = ModMatrix::new();
+        matrix.set_amount(NUM_MOD_SOURCES + ModSource::Velocity as usize, 0.5_f32);
+        matrix.set_amount(NUM_MOD_CELLS + 3, 2.0_f32);
+        let outputs = matrix.evaluate(&[1.0_f32; NUM_MOD_SOURCES]);
+        // out of range cells and amounts are clamped to the last cell and full scale
+        assert_eq!(outputs, [0.0, 0.5, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn matrices_round_trip_through_words() {
+        let mut edited = ModMatrix::new();
+        edited.set_amount(7, 0.3_f32);
+        edited.set_amount(NUM_MOD_CELLS - 1, -1.0_f32);
+
+        for matrix in [ModMatrix::lead(), ModMatrix::motion(), edited].iter() {
+            let mut loaded = ModMatrix::new();
+            assert!(loaded.load_words(&matrix.to_words()));
+            // the amounts are stored in steps of 1/127, so they come back to within half a step
+            for (a, b) in loaded
+                .amounts
+                .iter()
+                .flatten()
+                .zip(matrix.amounts.iter().flatten())
+            {
+                assert!((a - b).abs() <= 0.5 / 127.0, "{} {}", a, b);
+            }
+            assert_eq!(loaded.to_words(), matrix.to_words());
+        }
+    }
+
+    #[test]
+    fn erased_and_invalid_words_are_rejected() {
+        let words = ModMatrix::motion().to_words();
+
+        let mut invalid = Vec::new();
+        // erased flash
+        invalid.push([u64::MAX; MOD_MATRIX_NUM_WORDS]);
+        // some other data
+        let mut other = words;
+        other[0] += 1;
+        invalid.push(other);
+        // an amount byte which is never stored
+        let mut damaged = words;
+        damaged[2] |= (UNUSED_AMOUNT_BYTE as u64) << 24;
+        invalid.push(damaged);
+
+        for page in invalid.iter() {
+            let mut matrix = ModMatrix::lead();
+            assert!(!matrix.load_words(page));
+            assert_eq!(matrix.amounts, ModMatrix::lead().amounts);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+/// A release pitch-freeze which hides the smear in the ribbon reading as the finger lifts is represented here
+///
+/// As the finger lifts off of the softpot the contact gets lighter and the reading droops for the last few
+/// milliseconds before the lift is detected. While the finger is down the live position passes straight through, when
+/// the lift is detected the position is frozen at its value from a few milliseconds earlier.
+pub struct ReleaseFreeze {
+    history: [f32; MAX_LOOKBACK_SAMPLES],
+    next: usize,
+    lookback_samples: usize,
+    samples_in_touch: usize,
+    value: f32,
+}
+
+impl ReleaseFreeze {
+    /// `ReleaseFreeze::new(n)` is a new release freeze which freezes at the position from `n` samples before the lift
+    ///
+    /// The lookback is clamped to `MAX_LOOKBACK_SAMPLES`.
+    pub fn new(lookback_samples: usize) -> Self {
+        Self {
+            history: [0.0_f32; MAX_LOOKBACK_SAMPLES],
+            next: 0,
+            lookback_samples: lookback_samples.min(MAX_LOOKBACK_SAMPLES),
+            samples_in_touch: 0,
+            value: 0.0_f32,
+        }
+    }
+
+    /// `rf.set_lookback(n)` sets how many samples before the lift the position is frozen at
+    ///
+    /// The lookback is clamped to `MAX_LOOKBACK_SAMPLES`, zero disables the freeze.
+    pub fn set_lookback(&mut self, lookback_samples: usize) {
+        self.lookback_samples = lookback_samples.min(MAX_LOOKBACK_SAMPLES);
+    }
+
+    /// `rf.poll(p, f)` updates the freeze with the ribbon position `p`, `f` is true iff the finger is down
+    pub fn poll(&mut self, position: f32, finger_is_pressing: bool) {
+        if finger_is_pressing {
+            self.history[self.next] = position;
+            self.next = (self.next + 1) % MAX_LOOKBACK_SAMPLES;
+            self.samples_in_touch = (self.samples_in_touch + 1).min(MAX_LOOKBACK_SAMPLES);
+            self.value = position;
+        } else if 0 < self.samples_in_touch {
+            // the finger just lifted, don't look back past the start of a short tap
+            let lookback = self.lookback_samples.min(self.samples_in_touch);
+            if 0 < lookback {
+                let idx = (self.next + MAX_LOOKBACK_SAMPLES - lookback) % MAX_LOOKBACK_SAMPLES;
+                self.value = self.history[idx];
+            }
+            self.samples_in_touch = 0;
+        }
+    }
+
+    /// `rf.value()` is the live ribbon position while the finger is down, or the frozen position after it lifts
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+}
+
+/// The maximum number of samples to look back when freezing, 16ms at the fast ribbon sample rate
+pub const MAX_LOOKBACK_SAMPLES: usize = 16;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A finger held at the middle of the ribbon for 20ms, then the last 4ms of a lift-off where the reading droops as
+    /// the contact gets lighter, at the fast ribbon sample rate
+    const LIFT_OFF: [f32; 24] = [
+        0.500, 0.501, 0.500, 0.499, 0.500, 0.500, 0.501, 0.500, 0.500, 0.499, 0.500, 0.500, 0.501,
+        0.500, 0.500, 0.499, 0.500, 0.500, 0.501, 0.500, 0.493, 0.471, 0.428, 0.352,
+    ];
+
+    /// `lift(rf, t)` polls the release freeze `rf` with the pressed trace `t` followed by the lift
+    fn lift(rf: &mut ReleaseFreeze, trace: &[f32]) {
+        for &position in trace.iter() {
+            rf.poll(position, true);
+        }
+        rf.poll(0.0_f32, false);
+    }
+
+    #[test]
+    fn passes_the_live_position_while_pressed() {
+        let mut rf = ReleaseFreeze::new(5);
+        for &position in LIFT_OFF.iter() {
+            rf.poll(position, true);
+            assert_eq!(rf.value(), position);
+        }
+    }
+
+    #[test]
+    fn freezes_before_the_droop() {
+        let mut rf = ReleaseFreeze::new(5);
+        lift(&mut rf, &LIFT_OFF);
+        let frozen = rf.value();
+        assert!((frozen - 0.5).abs() <= 0.001, "{}", frozen);
+
+        // the frozen pitch doesn't drift while the finger stays up
+        for _ in 0..100 {
+            rf.poll(0.0_f32, false);
+            assert_eq!(rf.value(), frozen);
+        }
+    }
+
+    #[test]
+    fn no_lookback_keeps_the_droop() {
+        let mut rf = ReleaseFreeze::new(0);
+        lift(&mut rf, &LIFT_OFF);
+        assert_eq!(rf.value(), 0.352);
+    }
+
+    #[test]
+    fn short_taps_freeze_at_their_first_sample() {
+        let mut rf = ReleaseFreeze::new(MAX_LOOKBACK_SAMPLES);
+        lift(&mut rf, &LIFT_OFF[17..]);
+        assert_eq!(rf.value(), LIFT_OFF[17]);
+    }
+}
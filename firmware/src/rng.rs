@@ -0,0 +1,30 @@
+/// A small and fast pseudo-random number generator is represented here
+///
+/// This is a 32 bit xorshift generator, it is not suitable for anything but musical randomness.
+pub struct Rng {
+    state: u32,
+}
+
+impl Rng {
+    /// `Rng::new(s)` is a new random number generator seeded with `s`, a seed of zero is replaced with a non-zero seed
+    pub fn new(seed: u32) -> Self {
+        Self {
+            state: if seed == 0 { 0x2545_F491 } else { seed },
+        }
+    }
+
+    /// `rng.next_u32()` is the next pseudo-random integer
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    /// `rng.next_below(n)` is the next pseudo-random integer in `[0, n)`, `n` must be greater than zero
+    pub fn next_below(&mut self, n: usize) -> usize {
+        self.next_u32() as usize % n
+    }
+}
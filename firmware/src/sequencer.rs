@@ -0,0 +1,493 @@
+use crate::{arpeggiator::ArpClockSource, board::DAC8164_MAX_VOLTS};
+
+/// A monophonic step sequencer whose notes are entered by tapping the ribbon is represented here
+///
+/// In record mode each tap enters a note at the next step, wrapping around at the sequence length. In play mode the
+/// steps are clocked by the internal tempo or the MIDI clock, and each step plays its note with its own gate length,
+/// tie, and optional VCF CV.
+pub struct Sequencer {
+    steps: [Step; MAX_STEPS],
+    len: usize,
+    mode: SequencerMode,
+    record_idx: usize,
+
+    clock_source: ArpClockSource,
+    tick_rate_hz: f32,
+    tempo_bpm: f32,
+    internal_phase: f32,
+    midi_clock_running: bool,
+    midi_clocks_since_step: u32,
+    ticks_since_step: u32,
+    step_period_ticks: u32,
+
+    play_idx: usize,
+    stepped: bool,
+}
+
+/// A single step of the sequence is represented here
+///
+/// * `note` - the note in semitones above 0 volts
+///
+/// * `gate_length` - the gate length as a fraction of the step in `[0.0, 1.0]`, zero is a rest
+///
+/// * `tie` - true iff the gate is held open into the next step without retriggering
+///
+/// * `vcf` - the optional VCF CV in volts for the step
+#[derive(Clone, Copy)]
+pub struct Step {
+    pub note: u8,
+    pub gate_length: f32,
+    pub tie: bool,
+    pub vcf: Option<f32>,
+}
+
+/// The sequencer is either off, entering notes from the ribbon, or playing the sequence
+#[derive(Clone, Copy, PartialEq)]
+pub enum SequencerMode {
+    Off,
+    Record,
+    Play,
+}
+
+impl Sequencer {
+    /// `Sequencer::new(r)` is a new sequencer with an empty sequence, which will be ticked at `r` hertz
+    pub fn new(tick_rate_hz: u32) -> Self {
+        Self {
+            steps: [Step::REST; MAX_STEPS],
+            len: MIN_STEPS,
+            mode: SequencerMode::Off,
+            record_idx: 0,
+
+            clock_source: ArpClockSource::Internal,
+            tick_rate_hz: tick_rate_hz as f32,
+            tempo_bpm: 120.0_f32,
+            internal_phase: 0.0_f32,
+            // the clock is followed until a MIDI stop message, not all gear sends a start message
+            midi_clock_running: true,
+            midi_clocks_since_step: 0,
+            ticks_since_step: 0,
+            step_period_ticks: tick_rate_hz,
+
+            play_idx: 0,
+            stepped: false,
+        }
+    }
+
+    /// `seq.set_mode(m)` sets the sequencer mode to `m`
+    ///
+    /// Entering record mode starts entering notes at the first step, entering play mode starts the sequence over.
+    pub fn set_mode(&mut self, mode: SequencerMode) {
+        if mode == self.mode {
+            return;
+        }
+        self.mode = mode;
+        match mode {
+            SequencerMode::Record => self.record_idx = 0,
+            SequencerMode::Play => self.restart(),
+            SequencerMode::Off => (),
+        }
+    }
+
+    /// `seq.set_len(n)` sets the number of steps in the sequence, clamped to `[MIN_STEPS, MAX_STEPS]`
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len.clamp(MIN_STEPS, MAX_STEPS);
+        self.record_idx %= self.len;
+        self.play_idx %= self.len;
+    }
+
+    /// `seq.len()` is the number of steps in the sequence
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// `seq.set_clock_source(c)` sets whether the steps follow the internal tempo or the MIDI clock
+    pub fn set_clock_source(&mut self, clock_source: ArpClockSource) {
+        self.clock_source = clock_source;
+    }
+
+    /// `seq.set_tempo_bpm(t)` sets the internal tempo to `t` beats per minute, the steps are sixteenth notes
+    pub fn set_tempo_bpm(&mut self, tempo_bpm: f32) {
+        self.tempo_bpm = tempo_bpm.max(1.0_f32);
+    }
+
+    /// `seq.enter_step(s)` stores the step `s` at the record position and moves to the next step, if recording
+    pub fn enter_step(&mut self, step: Step) {
+        if self.mode != SequencerMode::Record {
+            return;
+        }
+        self.steps[self.record_idx] = step;
+        self.record_idx = (self.record_idx + 1) % self.len;
+    }
+
+    /// `seq.tie_last_step()` ties the most recently entered step into the next one, if recording
+    pub fn tie_last_step(&mut self) {
+        if self.mode != SequencerMode::Record {
+            return;
+        }
+        let last_idx = (self.record_idx + self.len - 1) % self.len;
+        self.steps[last_idx].tie = true;
+    }
+
+    /// `seq.midi_clock()` advances the sequencer by one MIDI clock, there are 24 clocks per beat
+    pub fn midi_clock(&mut self) {
+        if self.mode != SequencerMode::Play
+            || self.clock_source != ArpClockSource::Midi
+            || !self.midi_clock_running
+        {
+            return;
+        }
+
+        self.midi_clocks_since_step += 1;
+        if MIDI_CLOCKS_PER_STEP <= self.midi_clocks_since_step {
+            self.midi_clocks_since_step = 0;
+            self.advance();
+        }
+    }
+
+    /// `seq.midi_start()` restarts the sequence and starts following the MIDI clock
+    pub fn midi_start(&mut self) {
+        self.restart();
+        self.midi_clock_running = true;
+    }
+
+    /// `seq.midi_continue()` resumes following the MIDI clock from where it stopped
+    pub fn midi_continue(&mut self) {
+        self.midi_clock_running = true;
+    }
+
+    /// `seq.midi_stop()` stops following the MIDI clock
+    pub fn midi_stop(&mut self) {
+        self.midi_clock_running = false;
+    }
+
+    /// `seq.tick()` advances the internal clock and the gate timing
+    ///
+    /// It is required to call this function at the tick rate given when the sequencer was created.
+    pub fn tick(&mut self) {
+        self.ticks_since_step = self.ticks_since_step.saturating_add(1);
+
+        if self.mode == SequencerMode::Play && self.clock_source == ArpClockSource::Internal {
+            let steps_per_tick =
+                self.tempo_bpm * STEPS_PER_BEAT as f32 / (60.0_f32 * self.tick_rate_hz);
+            self.internal_phase += steps_per_tick;
+            if 1.0_f32 <= self.internal_phase {
+                self.internal_phase -= 1.0_f32;
+                self.advance();
+            }
+        }
+    }
+
+    /// `seq.is_playing()` is true iff the sequence is playing, so the sequencer should be heard
+    pub fn is_playing(&self) -> bool {
+        self.mode == SequencerMode::Play
+    }
+
+    /// `seq.note()` is the note of the current step in semitones above 0 volts
+    pub fn note(&self) -> u8 {
+        self.steps[self.play_idx].note
+    }
+
+    /// `seq.gate()` is the sequencer gate, high for the gate length of each step or through the whole step if tied
+    pub fn gate(&self) -> bool {
+        let step = &self.steps[self.play_idx];
+        if !self.is_playing() || step.gate_length <= 0.0_f32 {
+            return false;
+        }
+        step.tie
+            || (self.ticks_since_step as f32) < step.gate_length * self.step_period_ticks as f32
+    }
+
+    /// `seq.vcf()` is the optional VCF CV in volts of the current step
+    pub fn vcf(&self) -> Option<f32> {
+        self.steps[self.play_idx].vcf
+    }
+
+    /// `seq.take_step()` is true iff a new untied note has started since the last call, self clearing
+    pub fn take_step(&mut self) -> bool {
+        let stepped = self.stepped;
+        self.stepped = false;
+        stepped
+    }
+
+    /// `seq.to_words()` is the sequence packed into words for storing in flash
+    pub fn to_words(&self) -> [u64; SEQUENCE_NUM_WORDS] {
+        let mut words = [0; SEQUENCE_NUM_WORDS];
+        words[0] = (STORAGE_MAGIC as u64) << 32 | self.len as u64;
+        for (i, pair) in self.steps.chunks(2).enumerate() {
+            words[i + 1] = (pack_step(&pair[1]) as u64) << 32 | pack_step(&pair[0]) as u64;
+        }
+        words
+    }
+
+    /// `seq.load_words(ws)` replaces the sequence with the one packed into the words `ws`
+    ///
+    /// # Returns:
+    ///
+    /// * true iff the words held a stored sequence, erased flash or garbage is ignored
+    pub fn load_words(&mut self, words: &[u64; SEQUENCE_NUM_WORDS]) -> bool {
+        let len = words[0] as u32 as usize;
+        if (words[0] >> 32) as u32 != STORAGE_MAGIC
+            || !(MIN_STEPS..=MAX_STEPS).contains(&len)
+            || words[1..].iter().any(|&word| word & UNUSED_STEP_BITS != 0)
+        {
+            return false;
+        }
+        for (i, pair) in self.steps.chunks_mut(2).enumerate() {
+            let word = words[i + 1];
+            pair[0] = unpack_step(word as u32);
+            pair[1] = unpack_step((word >> 32) as u32);
+        }
+        self.set_len(len);
+        true
+    }
+
+    /// `seq.restart()` starts the sequence over from the first step
+    fn restart(&mut self) {
+        self.internal_phase = 0.0_f32;
+        self.midi_clocks_since_step = 0;
+        self.advance_to(0);
+    }
+
+    /// `seq.advance()` moves to the next step of the sequence
+    fn advance(&mut self) {
+        // the step period is measured so that the gate length works with either clock source
+        self.step_period_ticks = self.ticks_since_step.max(1);
+        self.advance_to((self.play_idx + 1) % self.len);
+    }
+
+    /// `seq.advance_to(i)` moves to step `i` of the sequence and opens the gate
+    fn advance_to(&mut self, idx: usize) {
+        // a step following a tied step continues the same note
+        let prev_tied = self.steps[self.play_idx].tie && idx != 0;
+        self.play_idx = idx;
+        self.ticks_since_step = 0;
+        self.stepped = !prev_tied && 0.0_f32 < self.steps[idx].gate_length;
+    }
+}
+
+impl Step {
+    /// A step with no gate
+    pub const REST: Step = Step {
+        note: 0,
+        gate_length: 0.0_f32,
+        tie: false,
+        vcf: None,
+    };
+}
+
+/// The fewest steps a sequence can have
+pub const MIN_STEPS: usize = 16;
+
+/// The most steps a sequence can have
+pub const MAX_STEPS: usize = 32;
+
+/// The number of 64 bit words used to store a sequence, a header followed by two steps per word
+pub const SEQUENCE_NUM_WORDS: usize = 1 + MAX_STEPS / 2;
+
+/// Marks a word in flash as the header of a stored sequence
+const STORAGE_MAGIC: u32 = 0x5345_5131;
+
+/// The number of sequencer steps per beat, the steps are sixteenth notes
+const STEPS_PER_BEAT: u32 = 4;
+
+/// The MIDI clock has 24 pulses per beat
+const MIDI_CLOCKS_PER_STEP: u32 = 24 / STEPS_PER_BEAT;
+
+/// The largest value of the 14 bit VCF CV stored in each step
+const PACKED_VCF_MAX: u32 = (1 << 14) - 1;
+
+/// The top bit of each packed step is never set, a page which was erased and not fully programmed again has it set
+const UNUSED_STEP_BITS: u64 = 1 << 31 | 1 << 63;
+
+/// `pack_step(s)` is the step `s` packed into a single word
+///
+/// From the low bit up: 7 bits of note, 1 bit of tie, 8 bits of gate length, 1 bit of VCF presence, 14 bits of VCF.
+fn pack_step(step: &Step) -> u32 {
+    let gate_length = (step.gate_length.clamp(0.0_f32, 1.0_f32) * 255.0_f32) as u32;
+    let vcf = match step.vcf {
+        Some(v) => {
+            let v = (v / DAC8164_MAX_VOLTS).clamp(0.0_f32, 1.0_f32);
+            1 | ((v * PACKED_VCF_MAX as f32) as u32) << 1
+        }
+        None => 0,
+    };
+    (step.note & 0x7F) as u32 | (step.tie as u32) << 7 | gate_length << 8 | vcf << 16
+}
+
+/// `unpack_step(w)` is the step packed into the word `w`
+fn unpack_step(word: u32) -> Step {
+    let vcf = word >> 16;
+    Step {
+        note: (word & 0x7F) as u8,
+        tie: word & (1 << 7) != 0,
+        gate_length: ((word >> 8) & 0xFF) as f32 / 255.0_f32,
+        vcf: if vcf & 1 != 0 {
+            Some(((vcf >> 1) & PACKED_VCF_MAX) as f32 / PACKED_VCF_MAX as f32 * DAC8164_MAX_VOLTS)
+        } else {
+            None
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTE: Step = Step {
+        note: 60,
+        gate_length: 0.5_f32,
+        tie: false,
+        vcf: None,
+    };
+
+    const TIED_NOTE: Step = Step {
+        note: 62,
+        gate_length: 0.25_f32,
+        tie: true,
+        vcf: Some(2.5_f32),
+    };
+
+    /// `recorded(ss)` is a MIDI clocked sequencer playing the steps `ss` entered in record mode
+    fn recorded(steps: &[Step]) -> Sequencer {
+        let mut seq = Sequencer::new(1_000);
+        seq.set_clock_source(ArpClockSource::Midi);
+        seq.set_mode(SequencerMode::Record);
+        for &step in steps.iter() {
+            seq.enter_step(step);
+        }
+        seq.set_mode(SequencerMode::Play);
+        seq
+    }
+
+    /// `step(seq)` ticks the sequencer `seq` through one 100 tick step of MIDI clocks
+    ///
+    /// # Returns:
+    ///
+    /// * the number of ticks the gate was high for
+    fn step(seq: &mut Sequencer) -> usize {
+        let mut high_ticks = 0;
+        for _ in 0..100 {
+            high_ticks += seq.gate() as usize;
+            seq.tick();
+        }
+        for _ in 0..MIDI_CLOCKS_PER_STEP {
+            seq.midi_clock();
+        }
+        high_ticks
+    }
+
+    /// `assert_same_step(a, b)` asserts that the steps `a` and `b` match, up to the packed resolution
+    fn assert_same_step(a: &Step, b: &Step) {
+        assert_eq!(a.note, b.note);
+        assert_eq!(a.tie, b.tie);
+        assert!((a.gate_length - b.gate_length).abs() <= 1.0 / 255.0);
+        match (a.vcf, b.vcf) {
+            (Some(a), Some(b)) => {
+                assert!((a - b).abs() <= DAC8164_MAX_VOLTS / PACKED_VCF_MAX as f32)
+            }
+            (None, None) => (),
+            _ => panic!("only one step has a VCF CV"),
+        }
+    }
+
+    #[test]
+    fn steps_pack_and_unpack() {
+        for step in [NOTE, TIED_NOTE, Step::REST].iter() {
+            let word = pack_step(step);
+            assert_eq!(word as u64 & UNUSED_STEP_BITS, 0);
+            assert_same_step(&unpack_step(word), step);
+        }
+    }
+
+    #[test]
+    fn sequences_round_trip_through_words() {
+        let mut seq = recorded(&[NOTE, Step::REST, TIED_NOTE, NOTE]);
+        seq.set_len(20);
+
+        let mut loaded = Sequencer::new(1_000);
+        assert!(loaded.load_words(&seq.to_words()));
+        assert_eq!(loaded.len(), 20);
+        for (a, b) in loaded.steps.iter().zip(seq.steps.iter()) {
+            assert_same_step(a, b);
+        }
+        assert_eq!(loaded.to_words(), seq.to_words());
+    }
+
+    #[test]
+    fn erased_and_corrupted_pages_are_rejected() {
+        let words = recorded(&[NOTE, TIED_NOTE]).to_words();
+
+        let mut corrupted = Vec::new();
+        // erased flash
+        corrupted.push([u64::MAX; SEQUENCE_NUM_WORDS]);
+        // some other data
+        let mut other = words;
+        other[0] ^= 1 << 40;
+        corrupted.push(other);
+        // a length which can't be set
+        let mut too_long = words;
+        too_long[0] += MAX_STEPS as u64;
+        corrupted.push(too_long);
+        // a page which was erased and only partly programmed again
+        let mut partial = words;
+        partial[SEQUENCE_NUM_WORDS - 1] = u64::MAX;
+        corrupted.push(partial);
+
+        for page in corrupted.iter() {
+            let mut seq = recorded(&[NOTE]);
+            let before = seq.to_words();
+            assert!(!seq.load_words(page));
+            assert_eq!(seq.to_words(), before);
+        }
+    }
+
+    #[test]
+    fn steps_advance_and_wrap() {
+        let mut seq = recorded(&[NOTE, Step::REST, Step { note: 64, ..NOTE }]);
+        assert!(seq.take_step());
+        assert_eq!(seq.note(), 60);
+        step(&mut seq);
+
+        // a rest has no gate and starts no note
+        assert!(!seq.take_step());
+        assert_eq!(step(&mut seq), 0);
+
+        // the gate length is a fraction of the measured step period
+        assert!(seq.take_step());
+        assert_eq!(seq.note(), 64);
+        assert_eq!(step(&mut seq), 50);
+        assert!(!seq.take_step());
+
+        for _ in 3..MIN_STEPS {
+            step(&mut seq);
+        }
+        assert!(seq.take_step());
+        assert_eq!(seq.note(), 60);
+    }
+
+    #[test]
+    fn tied_steps_hold_the_gate_into_the_next_note() {
+        let mut seq = recorded(&[NOTE, TIED_NOTE, NOTE]);
+        step(&mut seq);
+        assert!(seq.take_step());
+        assert_eq!(seq.vcf(), TIED_NOTE.vcf);
+        // the tie holds the gate through the whole step despite its short gate length
+        assert_eq!(step(&mut seq), 100);
+
+        // the next note starts without a new step so the gate isn't retriggered
+        assert!(!seq.take_step());
+        assert!(seq.gate());
+        assert_eq!(seq.note(), 60);
+    }
+
+    #[test]
+    fn the_last_entered_step_can_be_tied() {
+        let mut seq = Sequencer::new(1_000);
+        seq.set_mode(SequencerMode::Record);
+        seq.enter_step(NOTE);
+        seq.tie_last_step();
+        seq.enter_step(NOTE);
+        assert!(seq.steps[0].tie);
+        assert!(!seq.steps[1].tie);
+    }
+}
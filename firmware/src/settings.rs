@@ -0,0 +1,774 @@
+use crate::{
+    arpeggiator::{ArpClockSource, ArpOrder, ArpRibbonMode, MAX_OCTAVES},
+    board::{Dac8164Channel, DAC8164_MAX_VOLTS},
+    chord_strum::{ChordSize, Scale},
+    envelope::EnvelopeMode,
+    gate::{GateMerge, GateMode},
+    lfo::{AuxSource, LfoSync, LfoWaveform, MAX_RATE_HZ, MIN_RATE_HZ},
+    looper::LooperOverdub,
+    midi_out::MidiOutMode,
+    mod_matrix::{ModRouting, NUM_MOD_CELLS},
+    sequencer::{SequencerMode, MAX_STEPS, MIN_STEPS},
+    smoothing::PotSmoothing,
+    takeover::TakeoverMode,
+    ui::{LevelPot, PotCurve, PotResponse, VcoPotMode, NUM_LEVEL_POTS},
+};
+
+/// The user settings which have no dedicated panel control are represented here
+///
+/// Without a display these settings are adjusted over MIDI with control change messages, see the `CC_*` constants.
+pub struct Settings {
+    gate_mode: GateMode,
+    gate_retrigger_ms: u32,
+    gate_trigger_ms: u32,
+    gate_merge: GateMerge,
+    jump_retriggers_gate: bool,
+    min_gate_ms: u32,
+    release_freeze_ms: u32,
+    arp_enabled: bool,
+    arp_order: ArpOrder,
+    arp_num_octaves: u8,
+    arp_gate_length: f32,
+    arp_tempo_bpm: f32,
+    arp_clock_source: ArpClockSource,
+    arp_ribbon_mode: ArpRibbonMode,
+    strum_enabled: bool,
+    root: u8,
+    scale: Scale,
+    chord_size: ChordSize,
+    looper_overdub: LooperOverdub,
+    seq_mode: SequencerMode,
+    seq_len: usize,
+    seq_gate_length: f32,
+    seq_records_vcf: bool,
+    aux_source: AuxSource,
+    lfo_waveform: LfoWaveform,
+    lfo_rate_hz: f32,
+    lfo_sync: LfoSync,
+    lfo_delay_ms: u32,
+    lfo_key_reset: bool,
+    env_mode: EnvelopeMode,
+    env_attack_ms: u32,
+    env_decay_ms: u32,
+    env_sustain: f32,
+    env_release_ms: u32,
+    env_channel: Option<Dac8164Channel>,
+    env_amount: f32,
+    touch_velocity_channel: Option<Dac8164Channel>,
+    slide_speed_channel: Option<Dac8164Channel>,
+    modosc_pot_bipolar: bool,
+    vcf_pot_bipolar: bool,
+    delay_pot_bipolar: bool,
+    bipolar_center_volts: f32,
+    pot_responses: [PotResponse; NUM_LEVEL_POTS],
+    selected_pot: LevelPot,
+    pot_smoothing: PotSmoothing,
+    takeover_mode: TakeoverMode,
+    mod_routing: ModRouting,
+    mod_cell: usize,
+    midi_out_mode: MidiOutMode,
+    midi_out_bend_range: u8,
+    vco_pot_mode: VcoPotMode,
+    bender_enabled: bool,
+    bender_range_semitones: u8,
+    latch_holds_gate: bool,
+    latch_double_tap: bool,
+}
+
+impl Settings {
+    /// `Settings::new()` is new settings initialized to default values.
+    pub fn new() -> Self {
+        Self {
+            gate_mode: GateMode::Legato,
+            gate_retrigger_ms: 5,
+            gate_trigger_ms: 10,
+            gate_merge: GateMerge::Or,
+            jump_retriggers_gate: true,
+            min_gate_ms: 20,
+            release_freeze_ms: 3,
+            arp_enabled: false,
+            arp_order: ArpOrder::Up,
+            arp_num_octaves: 1,
+            arp_gate_length: 0.5_f32,
+            arp_tempo_bpm: 120.0_f32,
+            arp_clock_source: ArpClockSource::Internal,
+            arp_ribbon_mode: ArpRibbonMode::Transpose,
+            strum_enabled: false,
+            root: 0,
+            scale: Scale::Major,
+            chord_size: ChordSize::Triad,
+            looper_overdub: LooperOverdub::Transpose,
+            seq_mode: SequencerMode::Off,
+            seq_len: MIN_STEPS,
+            seq_gate_length: 0.5_f32,
+            seq_records_vcf: false,
+            aux_source: AuxSource::Ribbon,
+            lfo_waveform: LfoWaveform::Sine,
+            lfo_rate_hz: 1.0_f32,
+            lfo_sync: LfoSync::Free,
+            lfo_delay_ms: 0,
+            lfo_key_reset: false,
+            env_mode: EnvelopeMode::Adsr,
+            env_attack_ms: 10,
+            env_decay_ms: 200,
+            env_sustain: 0.5_f32,
+            env_release_ms: 300,
+            env_channel: None,
+            env_amount: 1.0_f32,
+            touch_velocity_channel: None,
+            slide_speed_channel: None,
+            modosc_pot_bipolar: false,
+            vcf_pot_bipolar: false,
+            delay_pot_bipolar: false,
+            bipolar_center_volts: DAC8164_MAX_VOLTS / 2.0_f32,
+            pot_responses: [PotResponse::LINEAR; NUM_LEVEL_POTS],
+            selected_pot: LevelPot::Vco,
+            pot_smoothing: PotSmoothing::Biquad,
+            takeover_mode: TakeoverMode::Pickup,
+            mod_routing: ModRouting::Fixed,
+            mod_cell: 0,
+            midi_out_mode: MidiOutMode::Off,
+            midi_out_bend_range: 2,
+            vco_pot_mode: VcoPotMode::Attenuate,
+            bender_enabled: false,
+            bender_range_semitones: 12,
+            latch_holds_gate: true,
+            latch_double_tap: false,
+        }
+    }
+
+    /// `settings.handle_cc(c, v)` updates the settings with the MIDI control change `c` set to value `v`
+    ///
+    /// # Returns:
+    ///
+    /// * true iff the control change was one of the settings controls
+    pub fn handle_cc(&mut self, control: u8, value: u8) -> bool {
+        match control {
+            CC_GATE_MODE => {
+                self.gate_mode = match cc_to_option(value, 3) {
+                    0 => GateMode::Legato,
+                    1 => GateMode::Retrigger,
+                    _ => GateMode::Trigger,
+                }
+            }
+            CC_GATE_RETRIGGER_MS => self.gate_retrigger_ms = value as u32 + 1,
+            CC_GATE_TRIGGER_MS => self.gate_trigger_ms = value as u32 + 1,
+            CC_GATE_MERGE => {
+                self.gate_merge = match cc_to_option(value, 5) {
+                    0 => GateMerge::Or,
+                    1 => GateMerge::RibbonOnly,
+                    2 => GateMerge::MidiOnly,
+                    3 => GateMerge::And,
+                    _ => GateMerge::MidiGatesRibbonBends,
+                }
+            }
+            CC_JUMP_RETRIGGERS_GATE => self.jump_retriggers_gate = cc_to_switch(value),
+            CC_MIN_GATE_MS => self.min_gate_ms = value as u32 * 2,
+            CC_RELEASE_FREEZE_MS => {
+                self.release_freeze_ms = cc_to_option(value, MAX_RELEASE_FREEZE_MS as u8 + 1) as u32
+            }
+            CC_ARP_ENABLED => self.arp_enabled = cc_to_switch(value),
+            CC_ARP_ORDER => {
+                self.arp_order = match cc_to_option(value, 5) {
+                    0 => ArpOrder::Up,
+                    1 => ArpOrder::Down,
+                    2 => ArpOrder::UpDown,
+                    3 => ArpOrder::Random,
+                    _ => ArpOrder::AsPlayed,
+                }
+            }
+            CC_ARP_OCTAVES => self.arp_num_octaves = cc_to_option(value, MAX_OCTAVES) + 1,
+            CC_ARP_GATE_LENGTH => self.arp_gate_length = value as f32 / 127.0_f32,
+            CC_ARP_TEMPO => self.arp_tempo_bpm = 40.0_f32 + value as f32 * 2.0_f32,
+            CC_ARP_CLOCK_SOURCE => {
+                self.arp_clock_source = if cc_to_switch(value) {
+                    ArpClockSource::Midi
+                } else {
+                    ArpClockSource::Internal
+                }
+            }
+            CC_ARP_RIBBON_MODE => {
+                self.arp_ribbon_mode = if cc_to_switch(value) {
+                    ArpRibbonMode::Scrub
+                } else {
+                    ArpRibbonMode::Transpose
+                }
+            }
+            CC_STRUM_ENABLED => self.strum_enabled = cc_to_switch(value),
+            CC_ROOT => self.root = cc_to_option(value, 12),
+            CC_SCALE => {
+                self.scale = if cc_to_switch(value) {
+                    Scale::NaturalMinor
+                } else {
+                    Scale::Major
+                }
+            }
+            CC_CHORD_SIZE => {
+                self.chord_size = if cc_to_switch(value) {
+                    ChordSize::Seventh
+                } else {
+                    ChordSize::Triad
+                }
+            }
+            CC_LOOPER_OVERDUB => {
+                self.looper_overdub = if cc_to_switch(value) {
+                    LooperOverdub::LiveVcf
+                } else {
+                    LooperOverdub::Transpose
+                }
+            }
+            CC_SEQ_MODE => {
+                self.seq_mode = match cc_to_option(value, 3) {
+                    0 => SequencerMode::Off,
+                    1 => SequencerMode::Record,
+                    _ => SequencerMode::Play,
+                }
+            }
+            CC_SEQ_LENGTH => {
+                let num_lengths = (MAX_STEPS - MIN_STEPS + 1) as u8;
+                self.seq_len = MIN_STEPS + cc_to_option(value, num_lengths) as usize
+            }
+            CC_SEQ_GATE_LENGTH => self.seq_gate_length = value as f32 / 127.0_f32,
+            CC_SEQ_RECORDS_VCF => self.seq_records_vcf = cc_to_switch(value),
+            CC_AUX_SOURCE => {
+                self.aux_source = if cc_to_switch(value) {
+                    AuxSource::Lfo
+                } else {
+                    AuxSource::Ribbon
+                }
+            }
+            CC_LFO_WAVEFORM => {
+                self.lfo_waveform = match cc_to_option(value, 6) {
+                    0 => LfoWaveform::Sine,
+                    1 => LfoWaveform::Triangle,
+                    2 => LfoWaveform::SawUp,
+                    3 => LfoWaveform::SawDown,
+                    4 => LfoWaveform::Square,
+                    _ => LfoWaveform::SmoothRandom,
+                }
+            }
+            CC_LFO_RATE => {
+                // the rate is exponential so that the slow rates get as much of the range as the fast ones
+                let ratio = MAX_RATE_HZ / MIN_RATE_HZ;
+                self.lfo_rate_hz = MIN_RATE_HZ * libm::powf(ratio, value as f32 / 127.0_f32)
+            }
+            CC_LFO_SYNC => {
+                self.lfo_sync = match cc_to_option(value, 7) {
+                    0 => LfoSync::Free,
+                    1 => LfoSync::MidiClocks(6),
+                    2 => LfoSync::MidiClocks(12),
+                    3 => LfoSync::MidiClocks(24),
+                    4 => LfoSync::MidiClocks(48),
+                    5 => LfoSync::MidiClocks(96),
+                    _ => LfoSync::MidiClocks(192),
+                }
+            }
+            CC_LFO_DELAY_MS => self.lfo_delay_ms = value as u32 * 20,
+            CC_LFO_KEY_RESET => self.lfo_key_reset = cc_to_switch(value),
+            CC_ENV_MODE => {
+                self.env_mode = if cc_to_switch(value) {
+                    EnvelopeMode::Ar
+                } else {
+                    EnvelopeMode::Adsr
+                }
+            }
+            CC_ENV_ATTACK => self.env_attack_ms = cc_to_time_ms(value),
+            CC_ENV_DECAY => self.env_decay_ms = cc_to_time_ms(value),
+            CC_ENV_SUSTAIN => self.env_sustain = value as f32 / 127.0_f32,
+            CC_ENV_RELEASE => self.env_release_ms = cc_to_time_ms(value),
+            CC_ENV_CHANNEL => self.env_channel = cc_to_channel(value),
+            CC_ENV_AMOUNT => self.env_amount = value as f32 / 127.0_f32,
+            CC_TOUCH_VELOCITY_CHANNEL => self.touch_velocity_channel = cc_to_channel(value),
+            CC_SLIDE_SPEED_CHANNEL => self.slide_speed_channel = cc_to_channel(value),
+            CC_MODOSC_POT_BIPOLAR => self.modosc_pot_bipolar = cc_to_switch(value),
+            CC_VCF_POT_BIPOLAR => self.vcf_pot_bipolar = cc_to_switch(value),
+            CC_DELAY_POT_BIPOLAR => self.delay_pot_bipolar = cc_to_switch(value),
+            CC_BIPOLAR_CENTER => {
+                self.bipolar_center_volts = value as f32 / 127.0_f32 * DAC8164_MAX_VOLTS
+            }
+            CC_SELECTED_POT => {
+                self.selected_pot = match cc_to_option(value, 4) {
+                    0 => LevelPot::Vco,
+                    1 => LevelPot::ModOsc,
+                    2 => LevelPot::Vcf,
+                    _ => LevelPot::Delay,
+                }
+            }
+            CC_RESPONSE_CURVE => {
+                self.pot_responses[self.selected_pot as usize].curve = match cc_to_option(value, 4)
+                {
+                    0 => PotCurve::Linear,
+                    1 => PotCurve::AudioLog,
+                    2 => PotCurve::Exponential,
+                    _ => PotCurve::SCurve,
+                }
+            }
+            CC_RESPONSE_MIN => {
+                self.pot_responses[self.selected_pot as usize].min = value as f32 / 127.0_f32
+            }
+            CC_RESPONSE_MAX => {
+                self.pot_responses[self.selected_pot as usize].max = value as f32 / 127.0_f32
+            }
+            CC_POT_SMOOTHING => {
+                self.pot_smoothing = match cc_to_option(value, 3) {
+                    0 => PotSmoothing::Off,
+                    1 => PotSmoothing::OnePole,
+                    _ => PotSmoothing::Biquad,
+                }
+            }
+            CC_TAKEOVER_MODE => {
+                self.takeover_mode = match cc_to_option(value, 3) {
+                    0 => TakeoverMode::Jump,
+                    1 => TakeoverMode::Pickup,
+                    _ => TakeoverMode::ScaledCatch,
+                }
+            }
+            CC_MOD_ROUTING => {
+                self.mod_routing = match cc_to_option(value, 4) {
+                    0 => ModRouting::Fixed,
+                    1 => ModRouting::Lead,
+                    2 => ModRouting::Motion,
+                    _ => ModRouting::User,
+                }
+            }
+            CC_MOD_CELL => self.mod_cell = (value as usize).min(NUM_MOD_CELLS - 1),
+            CC_MIDI_OUT_MODE => {
+                self.midi_out_mode = match cc_to_option(value, 4) {
+                    0 => MidiOutMode::Off,
+                    1 => MidiOutMode::Notes,
+                    2 => MidiOutMode::NotesAndLevels,
+                    _ => MidiOutMode::Mpe,
+                }
+            }
+            CC_MIDI_OUT_BEND_RANGE => {
+                self.midi_out_bend_range = cc_to_option(value, MAX_BEND_RANGE_SEMITONES) + 1
+            }
+            CC_VCO_POT_MODE => {
+                self.vco_pot_mode = if cc_to_switch(value) {
+                    VcoPotMode::NoteSpan
+                } else {
+                    VcoPotMode::Attenuate
+                }
+            }
+            CC_BENDER_ENABLED => self.bender_enabled = cc_to_switch(value),
+            CC_BENDER_RANGE => {
+                self.bender_range_semitones = cc_to_option(value, MAX_BENDER_RANGE_SEMITONES) + 1
+            }
+            CC_LATCH_HOLDS_GATE => self.latch_holds_gate = cc_to_switch(value),
+            CC_LATCH_DOUBLE_TAP => self.latch_double_tap = cc_to_switch(value),
+            _ => return false,
+        }
+        true
+    }
+
+    /// `settings.gate_mode()` is the enumerated gate mode
+    pub fn gate_mode(&self) -> GateMode {
+        self.gate_mode
+    }
+
+    /// `settings.gate_retrigger_ms()` is the width of the retrigger blip in milliseconds
+    pub fn gate_retrigger_ms(&self) -> u32 {
+        self.gate_retrigger_ms
+    }
+
+    /// `settings.gate_trigger_ms()` is the width of the trigger pulse in milliseconds
+    pub fn gate_trigger_ms(&self) -> u32 {
+        self.gate_trigger_ms
+    }
+
+    /// `settings.gate_merge()` is the enumerated way that the ribbon and MIDI gates are merged
+    pub fn gate_merge(&self) -> GateMerge {
+        self.gate_merge
+    }
+
+    /// `settings.jump_retriggers_gate()` is true iff a two-finger jump on the ribbon should retrigger the gate
+    pub fn jump_retriggers_gate(&self) -> bool {
+        self.jump_retriggers_gate
+    }
+
+    /// `settings.min_gate_ms()` is the shortest time in milliseconds that the gate stays open after a note-on
+    pub fn min_gate_ms(&self) -> u32 {
+        self.min_gate_ms
+    }
+
+    /// `settings.release_freeze_ms()` is how many milliseconds before the finger lifts the ribbon pitch is frozen at
+    pub fn release_freeze_ms(&self) -> u32 {
+        self.release_freeze_ms
+    }
+
+    /// `settings.arp_enabled()` is true iff held MIDI notes should be arpeggiated
+    pub fn arp_enabled(&self) -> bool {
+        self.arp_enabled
+    }
+
+    /// `settings.arp_order()` is the enumerated order that the arpeggiator plays the held notes in
+    pub fn arp_order(&self) -> ArpOrder {
+        self.arp_order
+    }
+
+    /// `settings.arp_num_octaves()` is the number of octaves the arpeggio spans
+    pub fn arp_num_octaves(&self) -> u8 {
+        self.arp_num_octaves
+    }
+
+    /// `settings.arp_gate_length()` is the arpeggiator gate length as a fraction of each step
+    pub fn arp_gate_length(&self) -> f32 {
+        self.arp_gate_length
+    }
+
+    /// `settings.arp_tempo_bpm()` is the internal arpeggiator tempo in beats per minute
+    pub fn arp_tempo_bpm(&self) -> f32 {
+        self.arp_tempo_bpm
+    }
+
+    /// `settings.arp_clock_source()` is the enumerated clock source for the arpeggiator
+    pub fn arp_clock_source(&self) -> ArpClockSource {
+        self.arp_clock_source
+    }
+
+    /// `settings.arp_ribbon_mode()` is the enumerated way the ribbon interacts with the arpeggio
+    pub fn arp_ribbon_mode(&self) -> ArpRibbonMode {
+        self.arp_ribbon_mode
+    }
+
+    /// `settings.strum_enabled()` is true iff the ribbon zones should strum diatonic chords
+    pub fn strum_enabled(&self) -> bool {
+        self.strum_enabled
+    }
+
+    /// `settings.root()` is the root of the key in semitones above C
+    pub fn root(&self) -> u8 {
+        self.root
+    }
+
+    /// `settings.scale()` is the enumerated scale of the key
+    pub fn scale(&self) -> Scale {
+        self.scale
+    }
+
+    /// `settings.chord_size()` is the enumerated size of the strummed chords
+    pub fn chord_size(&self) -> ChordSize {
+        self.chord_size
+    }
+
+    /// `settings.looper_overdub()` is the enumerated way a live finger overdubs the playing loop
+    pub fn looper_overdub(&self) -> LooperOverdub {
+        self.looper_overdub
+    }
+
+    /// `settings.seq_mode()` is the enumerated step sequencer mode
+    pub fn seq_mode(&self) -> SequencerMode {
+        self.seq_mode
+    }
+
+    /// `settings.seq_len()` is the number of steps in the sequence
+    pub fn seq_len(&self) -> usize {
+        self.seq_len
+    }
+
+    /// `settings.set_seq_len(n)` sets the number of steps in the sequence, such as when a stored sequence is loaded
+    pub fn set_seq_len(&mut self, len: usize) {
+        self.seq_len = len.clamp(MIN_STEPS, MAX_STEPS);
+    }
+
+    /// `settings.seq_gate_length()` is the gate length given to newly entered steps, as a fraction of the step
+    pub fn seq_gate_length(&self) -> f32 {
+        self.seq_gate_length
+    }
+
+    /// `settings.seq_records_vcf()` is true iff newly entered steps store the VCF ribbon signal as their VCF CV
+    pub fn seq_records_vcf(&self) -> bool {
+        self.seq_records_vcf
+    }
+
+    /// `settings.aux_source()` is the enumerated signal carried by the AUX/Delay channel
+    pub fn aux_source(&self) -> AuxSource {
+        self.aux_source
+    }
+
+    /// `settings.lfo_waveform()` is the enumerated LFO waveform
+    pub fn lfo_waveform(&self) -> LfoWaveform {
+        self.lfo_waveform
+    }
+
+    /// `settings.lfo_rate_hz()` is the free running LFO rate in hertz
+    pub fn lfo_rate_hz(&self) -> f32 {
+        self.lfo_rate_hz
+    }
+
+    /// `settings.lfo_sync()` is the enumerated MIDI clock sync of the LFO
+    pub fn lfo_sync(&self) -> LfoSync {
+        self.lfo_sync
+    }
+
+    /// `settings.lfo_delay_ms()` is the time in milliseconds the LFO takes to fade in after each gate-on
+    pub fn lfo_delay_ms(&self) -> u32 {
+        self.lfo_delay_ms
+    }
+
+    /// `settings.lfo_key_reset()` is true iff each gate-on should reset the phase of the LFO
+    pub fn lfo_key_reset(&self) -> bool {
+        self.lfo_key_reset
+    }
+
+    /// `settings.env_mode()` is the enumerated mode of the modulation envelope
+    pub fn env_mode(&self) -> EnvelopeMode {
+        self.env_mode
+    }
+
+    /// `settings.env_attack_ms()` is the attack time of the modulation envelope in milliseconds
+    pub fn env_attack_ms(&self) -> u32 {
+        self.env_attack_ms
+    }
+
+    /// `settings.env_decay_ms()` is the decay time of the modulation envelope in milliseconds
+    pub fn env_decay_ms(&self) -> u32 {
+        self.env_decay_ms
+    }
+
+    /// `settings.env_sustain()` is the sustain level of the modulation envelope in `[0.0, 1.0]`
+    pub fn env_sustain(&self) -> f32 {
+        self.env_sustain
+    }
+
+    /// `settings.env_release_ms()` is the release time of the modulation envelope in milliseconds
+    pub fn env_release_ms(&self) -> u32 {
+        self.env_release_ms
+    }
+
+    /// `settings.env_channel()` is the DAC channel the modulation envelope is added to, if any
+    pub fn env_channel(&self) -> Option<Dac8164Channel> {
+        self.env_channel
+    }
+
+    /// `settings.env_amount()` is the depth of the modulation envelope as a fraction of the full DAC range
+    pub fn env_amount(&self) -> f32 {
+        self.env_amount
+    }
+
+    /// `settings.touch_velocity_channel()` is the DAC channel the touch velocity CV replaces, if any
+    pub fn touch_velocity_channel(&self) -> Option<Dac8164Channel> {
+        self.touch_velocity_channel
+    }
+
+    /// `settings.slide_speed_channel()` is the DAC channel the slide speed CV replaces, if any
+    pub fn slide_speed_channel(&self) -> Option<Dac8164Channel> {
+        self.slide_speed_channel
+    }
+
+    /// `settings.modosc_pot_bipolar()` is true iff the MODOSC pot is a bipolar attenuverter
+    pub fn modosc_pot_bipolar(&self) -> bool {
+        self.modosc_pot_bipolar
+    }
+
+    /// `settings.vcf_pot_bipolar()` is true iff the VCF pot is a bipolar attenuverter
+    pub fn vcf_pot_bipolar(&self) -> bool {
+        self.vcf_pot_bipolar
+    }
+
+    /// `settings.delay_pot_bipolar()` is true iff the Delay pot is a bipolar attenuverter
+    pub fn delay_pot_bipolar(&self) -> bool {
+        self.delay_pot_bipolar
+    }
+
+    /// `settings.bipolar_center_volts()` is the voltage that the bipolar pots are centered on
+    pub fn bipolar_center_volts(&self) -> f32 {
+        self.bipolar_center_volts
+    }
+
+    /// `settings.pot_response(c)` is the response curve and end stops of level pot `c`
+    pub fn pot_response(&self, control: LevelPot) -> PotResponse {
+        self.pot_responses[control as usize]
+    }
+
+    /// `settings.pot_smoothing()` is the enumerated kind of smoothing used for the level pots
+    pub fn pot_smoothing(&self) -> PotSmoothing {
+        self.pot_smoothing
+    }
+
+    /// `settings.takeover_mode()` is the enumerated way the level pots take control back after being set over MIDI
+    pub fn takeover_mode(&self) -> TakeoverMode {
+        self.takeover_mode
+    }
+
+    /// `settings.selected_pot()` is the level pot selected for editing its response and position
+    pub fn selected_pot(&self) -> LevelPot {
+        self.selected_pot
+    }
+
+    /// `settings.mod_routing()` is the enumerated routing of the modulation sources to the DAC channels
+    pub fn mod_routing(&self) -> ModRouting {
+        self.mod_routing
+    }
+
+    /// `settings.mod_cell()` is the cell of the user modulation matrix selected for editing
+    pub fn mod_cell(&self) -> usize {
+        self.mod_cell
+    }
+
+    /// `settings.midi_out_mode()` is the enumerated choice of what the MIDI output sends
+    pub fn midi_out_mode(&self) -> MidiOutMode {
+        self.midi_out_mode
+    }
+
+    /// `settings.midi_out_bend_range()` is the pitch bend range of the MIDI output in semitones
+    pub fn midi_out_bend_range(&self) -> u8 {
+        self.midi_out_bend_range
+    }
+
+    /// `settings.vco_pot_mode()` is the enumerated way the VCO level pot acts on the ribbon
+    pub fn vco_pot_mode(&self) -> VcoPotMode {
+        self.vco_pot_mode
+    }
+
+    /// `settings.bender_enabled()` is true iff the lower switch position selects the bend mode instead of smooth mode
+    pub fn bender_enabled(&self) -> bool {
+        self.bender_enabled
+    }
+
+    /// `settings.bender_range_semitones()` is the bend reached by sliding half the length of the ribbon away from the
+    /// first touch
+    pub fn bender_range_semitones(&self) -> f32 {
+        self.bender_range_semitones as f32
+    }
+
+    /// `settings.latch_holds_gate()` is true iff the latch holds the gate along with the pitch after the finger lifts
+    pub fn latch_holds_gate(&self) -> bool {
+        self.latch_holds_gate
+    }
+
+    /// `settings.latch_double_tap()` is true iff double-tapping the ribbon toggles the latch
+    pub fn latch_double_tap(&self) -> bool {
+        self.latch_double_tap
+    }
+}
+
+/// MIDI control change numbers for the settings, taken from the undefined range so they don't clash with other gear
+pub const CC_GATE_MODE: u8 = 102;
+pub const CC_GATE_RETRIGGER_MS: u8 = 103;
+pub const CC_GATE_TRIGGER_MS: u8 = 104;
+pub const CC_GATE_MERGE: u8 = 105;
+pub const CC_JUMP_RETRIGGERS_GATE: u8 = 106;
+pub const CC_MIN_GATE_MS: u8 = 107;
+pub const CC_RELEASE_FREEZE_MS: u8 = 108;
+pub const CC_ARP_ENABLED: u8 = 109;
+pub const CC_ARP_ORDER: u8 = 110;
+pub const CC_ARP_OCTAVES: u8 = 111;
+pub const CC_ARP_GATE_LENGTH: u8 = 112;
+pub const CC_ARP_TEMPO: u8 = 113;
+pub const CC_ARP_CLOCK_SOURCE: u8 = 114;
+pub const CC_ARP_RIBBON_MODE: u8 = 115;
+pub const CC_STRUM_ENABLED: u8 = 116;
+pub const CC_ROOT: u8 = 117;
+pub const CC_SCALE: u8 = 118;
+pub const CC_CHORD_SIZE: u8 = 119;
+pub const CC_LOOPER_OVERDUB: u8 = 21;
+pub const CC_SEQ_MODE: u8 = 22;
+pub const CC_SEQ_LENGTH: u8 = 23;
+pub const CC_SEQ_GATE_LENGTH: u8 = 24;
+pub const CC_SEQ_RECORDS_VCF: u8 = 25;
+pub const CC_AUX_SOURCE: u8 = 29;
+pub const CC_LFO_WAVEFORM: u8 = 30;
+pub const CC_LFO_RATE: u8 = 31;
+pub const CC_LFO_SYNC: u8 = 85;
+pub const CC_LFO_DELAY_MS: u8 = 86;
+pub const CC_LFO_KEY_RESET: u8 = 87;
+pub const CC_ENV_SUSTAIN: u8 = 3;
+pub const CC_ENV_MODE: u8 = 9;
+pub const CC_ENV_CHANNEL: u8 = 14;
+pub const CC_ENV_AMOUNT: u8 = 15;
+/// The touch velocity and slide speed destinations have the same options as the envelope's, but use effects depth
+/// numbers since the undefined range has run out
+pub const CC_TOUCH_VELOCITY_CHANNEL: u8 = 94;
+pub const CC_SLIDE_SPEED_CHANNEL: u8 = 95;
+pub const CC_MOD_ROUTING: u8 = 89;
+pub const CC_MOD_CELL: u8 = 90;
+
+/// The undefined range has run out, the remaining settings use the general purpose controller numbers
+pub const CC_BIPOLAR_CENTER: u8 = 18;
+pub const CC_MODOSC_POT_BIPOLAR: u8 = 80;
+pub const CC_VCF_POT_BIPOLAR: u8 = 81;
+pub const CC_DELAY_POT_BIPOLAR: u8 = 82;
+pub const CC_SELECTED_POT: u8 = 83;
+pub const CC_RESPONSE_CURVE: u8 = 19;
+
+/// The end stops of the selected pot, the pot smoothing, and the takeover mode use sound controller numbers which have
+/// no other use here
+pub const CC_RESPONSE_MIN: u8 = 77;
+pub const CC_RESPONSE_MAX: u8 = 78;
+pub const CC_POT_SMOOTHING: u8 = 79;
+pub const CC_TAKEOVER_MODE: u8 = 70;
+
+/// The MIDI output settings use the effect control numbers, which have no other use here
+pub const CC_MIDI_OUT_MODE: u8 = 12;
+pub const CC_MIDI_OUT_BEND_RANGE: u8 = 13;
+
+/// The ribbon pitch settings use the effects depth numbers, which have no other use here
+pub const CC_VCO_POT_MODE: u8 = 91;
+pub const CC_BENDER_ENABLED: u8 = 92;
+pub const CC_BENDER_RANGE: u8 = 93;
+
+/// The latch settings use the portamento numbers, which have no other use here
+pub const CC_LATCH_HOLDS_GATE: u8 = 84;
+pub const CC_LATCH_DOUBLE_TAP: u8 = 5;
+
+/// The envelope times use the standard sound controller numbers for release, attack, and decay time
+pub const CC_ENV_RELEASE: u8 = 72;
+pub const CC_ENV_ATTACK: u8 = 73;
+pub const CC_ENV_DECAY: u8 = 75;
+
+/// Momentary button controls rather than settings, each press of the button is a CC value of 64 or more
+///
+/// The looper arm button steps the looper to its next state, the tie and rest buttons edit the sequence while
+/// recording, and the save button stores the sequence in flash.
+pub const CC_LOOPER_ARM: u8 = 20;
+pub const CC_SEQ_TIE: u8 = 26;
+pub const CC_SEQ_REST: u8 = 27;
+pub const CC_SEQ_SAVE: u8 = 28;
+pub const CC_MOD_SAVE: u8 = 17;
+
+/// The amount for the selected cell of the user modulation matrix, centered at 64 for no modulation
+///
+/// This control edits the matrix directly rather than a setting. It uses a general purpose controller number, since
+/// the undefined range has run out.
+pub const CC_MOD_AMOUNT: u8 = 16;
+
+/// The position of the selected level pot, held until the physical pot takes over
+///
+/// Like the modulation amount this sets the pot directly rather than a setting, using a sound controller number.
+pub const CC_POT_POSITION: u8 = 71;
+
+/// The longest release freeze that can be selected
+pub const MAX_RELEASE_FREEZE_MS: u32 = 15;
+
+/// The widest bender range that can be selected, in semitones
+pub const MAX_BENDER_RANGE_SEMITONES: u8 = 24;
+
+/// The widest pitch bend range of the MIDI output that can be selected, in semitones
+pub const MAX_BEND_RANGE_SEMITONES: u8 = 48;
+
+/// `cc_to_option(v, n)` is the control change value `v` mapped to one of `n` evenly sized options in `[0, n)`
+fn cc_to_option(value: u8, num_options: u8) -> u8 {
+    ((value as u16 * num_options as u16) / 128) as u8
+}
+
+/// `cc_to_channel(v)` is the control change value `v` mapped to no DAC channel or one of the four DAC channels
+fn cc_to_channel(value: u8) -> Option<Dac8164Channel> {
+    match cc_to_option(value, 5) {
+        0 => None,
+        1 => Some(Dac8164Channel::A),
+        2 => Some(Dac8164Channel::B),
+        3 => Some(Dac8164Channel::C),
+        _ => Some(Dac8164Channel::D),
+    }
+}
+
+/// `cc_to_time_ms(v)` is the control change value `v` mapped exponentially to a time in `[1, 10_000]` milliseconds
+fn cc_to_time_ms(value: u8) -> u32 {
+    libm::powf(10_000.0_f32, value as f32 / 127.0_f32) as u32
+}
+
+/// `cc_to_switch(v)` is the control change value `v` treated as an on/off switch, the upper half of the range is on
+fn cc_to_switch(value: u8) -> bool {
+    64 <= value
+}
@@ -0,0 +1,72 @@
+use core::f32::consts::PI;
+
+use crate::smoothing::one_pole_coeff_from_cutoff;
+
+/// A slide speed tracker which follows how fast the finger moves along the ribbon is represented here
+///
+/// The slide speed is the absolute rate of change of the ribbon position, smoothed so that it can be used as a
+/// modulation signal. Fast slides give a high value, a still finger or no finger gives zero.
+pub struct SlideSpeed {
+    sample_rate_hz: f32,
+    smoothing_coeff: f32,
+    prev_position: Option<f32>,
+    smoothed_speed: f32,
+}
+
+impl SlideSpeed {
+    /// `SlideSpeed::new(sr, t)` is a new slide speed tracker polled at `sr` hertz and smoothed with time constant `t`
+    ///
+    /// # Arguments:
+    ///
+    /// * `sample_rate_hz` - the rate at which the tracker will be polled
+    ///
+    /// * `smoothing_time_sec` - the time constant of the smoothing filter, longer times give a smoother but slower
+    ///   signal
+    pub fn new(sample_rate_hz: f32, smoothing_time_sec: f32) -> Self {
+        Self {
+            sample_rate_hz,
+            smoothing_coeff: one_pole_coeff_from_cutoff(
+                sample_rate_hz,
+                1.0_f32 / (2.0_f32 * PI * smoothing_time_sec),
+            ),
+            prev_position: None,
+            smoothed_speed: 0.0_f32,
+        }
+    }
+
+    /// `slide_speed.poll(p, f)` updates the slide speed with the ribbon position `p`, `f` is true iff the finger is down
+    ///
+    /// It is required to call this function at the sample rate given when the tracker was created.
+    pub fn poll(&mut self, position: f32, finger_is_pressing: bool) {
+        let raw_speed = match (finger_is_pressing, self.prev_position) {
+            (true, Some(prev)) => {
+                let delta = position - prev;
+                // ribbon lengths per second, less a little bit so that a still finger reads as zero
+                (delta.max(-delta) * self.sample_rate_hz - NOISE_FLOOR).max(0.0_f32)
+            }
+            // the first sample of a touch has nothing to compare against, and the jump from the last touch isn't a slide
+            _ => 0.0_f32,
+        };
+
+        self.prev_position = if finger_is_pressing {
+            Some(position)
+        } else {
+            None
+        };
+
+        self.smoothed_speed += (raw_speed - self.smoothed_speed) * self.smoothing_coeff;
+    }
+
+    /// `slide_speed.value()` is the smoothed slide speed in `[0.0, 1.0]`
+    ///
+    /// Full scale is reached when sliding at `FULL_SCALE_SPEED` ribbon lengths per second or faster.
+    pub fn value(&self) -> f32 {
+        (self.smoothed_speed / FULL_SCALE_SPEED).min(1.0_f32)
+    }
+}
+
+/// The slide speed in ribbon lengths per second which gives the maximum output
+const FULL_SCALE_SPEED: f32 = 4.0_f32;
+
+/// Slide speeds below this many ribbon lengths per second are treated as jitter
+const NOISE_FLOOR: f32 = 0.05_f32;
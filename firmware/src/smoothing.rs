@@ -0,0 +1,288 @@
+use core::f32::consts::PI;
+
+use biquad::{Biquad, Coefficients, DirectForm2Transposed, ToHertz, Type, Q_BUTTERWORTH_F32};
+
+/// A low-pass smoothing filter for a front panel pot is represented here
+///
+/// The filter runs on the difference from the first reading, so it starts out settled on the pot position instead of
+/// rising from zero at power-up.
+pub struct PotSmoother {
+    smoothing: PotSmoothing,
+    one_pole_coeff: f32,
+    one_pole_val: f32,
+    biquad: DirectForm2Transposed<f32>,
+    initial_val: Option<f32>,
+}
+
+/// The ways that pot readings can be smoothed are represented here
+///
+/// * `Off` - the readings are used as-is
+///
+/// * `OnePole` - a one-pole low-pass, the cheapest filter with no overshoot
+///
+/// * `Biquad` - a two-pole Butterworth low-pass, which removes more jitter for the same response time
+#[derive(Clone, Copy)]
+pub enum PotSmoothing {
+    Off,
+    OnePole,
+    Biquad,
+}
+
+impl PotSmoother {
+    /// `PotSmoother::new(sr, fc)` is a new one-pole pot smoother polled at `sr` hertz with cutoff `fc` hertz
+    ///
+    /// # Arguments:
+    ///
+    /// * `sample_rate_hz` - the rate at which the smoother will be polled
+    ///
+    /// * `cutoff_hz` - the cutoff frequency of the filter, it is kept below the Nyquist frequency
+    pub fn new(sample_rate_hz: f32, cutoff_hz: f32) -> Self {
+        let cutoff_hz = cutoff_hz.min(sample_rate_hz * MAX_CUTOFF_RATIO);
+        let biquad_coeffs = Coefficients::<f32>::from_params(
+            Type::LowPass,
+            sample_rate_hz.hz(),
+            cutoff_hz.hz(),
+            Q_BUTTERWORTH_F32,
+        )
+        .unwrap();
+
+        Self {
+            smoothing: PotSmoothing::OnePole,
+            one_pole_coeff: one_pole_coeff_from_cutoff(sample_rate_hz, cutoff_hz),
+            one_pole_val: 0.0_f32,
+            biquad: DirectForm2Transposed::<f32>::new(biquad_coeffs),
+            initial_val: None,
+        }
+    }
+
+    /// `smoother.set_smoothing(s)` sets the kind of smoothing to use
+    pub fn set_smoothing(&mut self, smoothing: PotSmoothing) {
+        self.smoothing = smoothing;
+    }
+
+    /// `smoother.process(v)` is the smoothed pot reading after the new raw reading `v` in `[0.0, 1.0]`
+    ///
+    /// It is required to call this function at the sample rate given when the smoother was created.
+    pub fn process(&mut self, val: f32) -> f32 {
+        let initial_val = *self.initial_val.get_or_insert(val);
+        let x = val - initial_val;
+
+        // both filters always run so that switching between them doesn't make the value jump
+        self.one_pole_val += (x - self.one_pole_val) * self.one_pole_coeff;
+        let biquad_val = self.biquad.run(x);
+
+        let y = match self.smoothing {
+            PotSmoothing::Off => x,
+            PotSmoothing::OnePole => self.one_pole_val,
+            PotSmoothing::Biquad => biquad_val,
+        };
+
+        // the biquad can overshoot a little past the ends of the pot
+        (y + initial_val).clamp(0.0_f32, 1.0_f32)
+    }
+}
+
+/// An adaptive jitter filter for the ribbon position is represented here
+///
+/// The cutoff follows how fast the finger is moving. A still finger gets heavy smoothing to hold the pitch steady, and
+/// a moving finger gets light smoothing so that slides and vibrato don't lag behind.
+pub struct RibbonSmoother {
+    sample_rate_hz: f32,
+    min_cutoff_hz: f32,
+    max_cutoff_hz: f32,
+    speed_coeff: f32,
+    speed: f32,
+    value: f32,
+    prev_finger_is_pressing: bool,
+}
+
+impl RibbonSmoother {
+    /// `RibbonSmoother::new(sr, min, max)` is a new ribbon smoother polled at `sr` hertz
+    ///
+    /// # Arguments:
+    ///
+    /// * `sample_rate_hz` - the rate at which the smoother will be polled
+    ///
+    /// * `min_cutoff_hz` - the cutoff frequency when the finger is still
+    ///
+    /// * `max_cutoff_hz` - the cutoff frequency when the finger is moving at `FULL_SPEED` or faster, setting this to
+    ///   the same as the minimum gives a plain one-pole filter
+    pub fn new(sample_rate_hz: f32, min_cutoff_hz: f32, max_cutoff_hz: f32) -> Self {
+        Self {
+            sample_rate_hz,
+            min_cutoff_hz,
+            max_cutoff_hz: max_cutoff_hz.min(sample_rate_hz * MAX_CUTOFF_RATIO),
+            speed_coeff: one_pole_coeff_from_cutoff(sample_rate_hz, SPEED_CUTOFF_HZ),
+            speed: 0.0_f32,
+            value: 0.0_f32,
+            prev_finger_is_pressing: false,
+        }
+    }
+
+    /// `smoother.poll(p, f)` updates the smoother with the ribbon position `p`, `f` is true iff the finger is down
+    ///
+    /// It is required to call this function at the sample rate given when the smoother was created.
+    pub fn poll(&mut self, position: f32, finger_is_pressing: bool) {
+        let finger_just_pressed = finger_is_pressing && !self.prev_finger_is_pressing;
+        self.prev_finger_is_pressing = finger_is_pressing;
+
+        // a new touch starts right where the finger lands instead of gliding over from the last touch
+        if !finger_is_pressing || finger_just_pressed {
+            self.value = position;
+            self.speed = 0.0_f32;
+            return;
+        }
+
+        // the speed is estimated from how far the input is from the output, in ribbon lengths per second
+        let delta = position - self.value;
+        let raw_speed = delta.max(-delta) * self.sample_rate_hz;
+        self.speed += (raw_speed - self.speed) * self.speed_coeff;
+
+        let amount = (self.speed / FULL_SPEED).min(1.0_f32);
+        let cutoff_hz = self.min_cutoff_hz + (self.max_cutoff_hz - self.min_cutoff_hz) * amount;
+        self.value += delta * one_pole_coeff_from_cutoff(self.sample_rate_hz, cutoff_hz);
+    }
+
+    /// `smoother.value()` is the smoothed ribbon position
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+}
+
+/// The ribbon speed in ribbon lengths per second at which the ribbon smoother is at its maximum cutoff
+const FULL_SPEED: f32 = 0.5_f32;
+
+/// The cutoff frequency of the filter which smooths the ribbon speed estimate
+const SPEED_CUTOFF_HZ: f32 = 20.0_f32;
+
+/// Cutoff frequencies are kept a little below the Nyquist frequency
+const MAX_CUTOFF_RATIO: f32 = 0.45_f32;
+
+/// `one_pole_coeff_from_cutoff(sr, fc)` is the coefficient for a one-pole lowpass filter at sample rate `sr` with
+/// cutoff `fc`
+pub fn one_pole_coeff_from_cutoff(sample_rate_hz: f32, cutoff_hz: f32) -> f32 {
+    let w = 2.0_f32 * PI * cutoff_hz / sample_rate_hz;
+    w / (1.0_f32 + w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `step(smoother, from, to, n)` is the output of `smoother` for each of `n` readings of `to`, after settling on
+    /// `from`
+    fn step(smoother: &mut PotSmoother, from: f32, to: f32, n: usize) -> [f32; 200] {
+        let mut outs = [0.0_f32; 200];
+        assert_eq!(smoother.process(from), from);
+        for out in outs.iter_mut().take(n) {
+            *out = smoother.process(to);
+        }
+        outs
+    }
+
+    /// `pot_smoother(s)` is a pot smoother with smoothing `s`, polled at 1 kHz with a 10 Hz cutoff
+    ///
+    /// The one-pole filter has a time constant of 16 milliseconds, or 16 readings.
+    fn pot_smoother(smoothing: PotSmoothing) -> PotSmoother {
+        let mut smoother = PotSmoother::new(1_000.0_f32, 10.0_f32);
+        smoother.set_smoothing(smoothing);
+        smoother
+    }
+
+    #[test]
+    fn pot_smoother_starts_on_the_first_reading() {
+        for &smoothing in &[
+            PotSmoothing::Off,
+            PotSmoothing::OnePole,
+            PotSmoothing::Biquad,
+        ] {
+            let mut smoother = pot_smoother(smoothing);
+            assert_eq!(smoother.process(0.7_f32), 0.7_f32);
+            assert!((smoother.process(0.7_f32) - 0.7_f32).abs() < 1.0e-6_f32);
+        }
+    }
+
+    #[test]
+    fn pot_smoother_off_follows_steps() {
+        let mut smoother = pot_smoother(PotSmoothing::Off);
+        let outs = step(&mut smoother, 0.2_f32, 0.8_f32, 1);
+        assert!((outs[0] - 0.8_f32).abs() < 1.0e-6_f32);
+    }
+
+    #[test]
+    fn pot_smoother_one_pole_step_response() {
+        let mut smoother = pot_smoother(PotSmoothing::OnePole);
+        let outs = step(&mut smoother, 0.2_f32, 0.8_f32, 200);
+
+        // 63% of the way after one time constant, with no overshoot
+        let risen = (outs[15] - 0.2_f32) / 0.6_f32;
+        assert!((risen - 0.63_f32).abs() < 0.02_f32);
+        assert!(outs.windows(2).all(|w| w[0] < w[1] || w[1] == 0.8_f32));
+        assert!((outs[99] - 0.8_f32).abs() < 0.01_f32);
+    }
+
+    #[test]
+    fn pot_smoother_biquad_step_response() {
+        let mut smoother = pot_smoother(PotSmoothing::Biquad);
+        let outs = step(&mut smoother, 0.0_f32, 1.0_f32, 200);
+
+        // the small Butterworth overshoot is clipped at the end of the pot
+        assert!(outs.iter().all(|&y| (0.0_f32..=1.0_f32).contains(&y)));
+        assert!(outs[5] < 0.5_f32);
+        assert!(outs[100..].iter().all(|&y| (y - 1.0_f32).abs() < 0.005_f32));
+    }
+
+    /// `touch(smoother, p)` presses the finger on the ribbon at `p`
+    fn touch(smoother: &mut RibbonSmoother, position: f32) {
+        smoother.poll(position, false);
+        smoother.poll(position, true);
+    }
+
+    #[test]
+    fn ribbon_smoother_lands_without_gliding() {
+        let mut smoother = RibbonSmoother::new(1_000.0_f32, 5.0_f32, 100.0_f32);
+        touch(&mut smoother, 0.2_f32);
+        assert_eq!(smoother.value(), 0.2_f32);
+        touch(&mut smoother, 0.8_f32);
+        assert_eq!(smoother.value(), 0.8_f32);
+    }
+
+    #[test]
+    fn ribbon_smoother_fixed_cutoff_step_response() {
+        let mut smoother = RibbonSmoother::new(1_000.0_f32, 10.0_f32, 10.0_f32);
+        touch(&mut smoother, 0.5_f32);
+        for _ in 0..16 {
+            smoother.poll(0.6_f32, true);
+        }
+        let risen = (smoother.value() - 0.5_f32) / 0.1_f32;
+        assert!((risen - 0.63_f32).abs() < 0.02_f32);
+    }
+
+    #[test]
+    fn ribbon_smoother_tracks_slides_closely() {
+        // sliding at 2 ribbon lengths per second
+        let slide = |min_cutoff_hz, max_cutoff_hz| {
+            let mut smoother = RibbonSmoother::new(1_000.0_f32, min_cutoff_hz, max_cutoff_hz);
+            touch(&mut smoother, 0.2_f32);
+            let mut position = 0.2_f32;
+            for _ in 0..100 {
+                position += 0.002_f32;
+                smoother.poll(position, true);
+            }
+            position - smoother.value()
+        };
+        assert!(slide(5.0_f32, 100.0_f32) < 0.01_f32);
+        assert!(0.05_f32 < slide(5.0_f32, 5.0_f32));
+    }
+
+    #[test]
+    fn ribbon_smoother_holds_a_still_finger_steady() {
+        // a jitter of a tenth of a millimeter or so is heavily smoothed
+        let mut smoother = RibbonSmoother::new(1_000.0_f32, 5.0_f32, 100.0_f32);
+        touch(&mut smoother, 0.5_f32);
+        for _ in 0..5 {
+            smoother.poll(0.5001_f32, true);
+        }
+        assert!(smoother.value() < 0.50005_f32);
+    }
+}
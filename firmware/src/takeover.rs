@@ -0,0 +1,181 @@
+/// The takeover of a front panel pot after its value was set from somewhere else is represented here
+///
+/// Once a pot's value is set over MIDI the physical pot no longer agrees with it. The set value is held until the pot
+/// is moved, then the takeover mode decides how the pot takes control back. Changes smaller than the noise threshold
+/// don't count as moving the pot.
+pub struct PotTakeover {
+    mode: TakeoverMode,
+    value: f32,
+    anchor: Option<f32>,
+    tracking: bool,
+}
+
+/// The ways that a pot can take control back from a set value are represented here
+///
+/// * `Jump` - the value jumps to the pot position as soon as the pot is moved
+///
+/// * `Pickup` - the pot is ignored until it passes through the set value
+///
+/// * `ScaledCatch` - the value moves in the same direction as the pot, scaled so the two meet at the end of the range
+#[derive(Clone, Copy)]
+pub enum TakeoverMode {
+    Jump,
+    Pickup,
+    ScaledCatch,
+}
+
+impl PotTakeover {
+    /// `PotTakeover::new()` is a new pot takeover which follows the pot until a value is set
+    pub fn new() -> Self {
+        Self {
+            mode: TakeoverMode::Pickup,
+            value: 0.0_f32,
+            anchor: None,
+            tracking: true,
+        }
+    }
+
+    /// `takeover.set_mode(m)` sets the takeover mode to `m`
+    pub fn set_mode(&mut self, mode: TakeoverMode) {
+        self.mode = mode;
+    }
+
+    /// `takeover.set_value(v)` sets the value to `v` in `[0.0, 1.0]`, it is held until the pot takes over
+    pub fn set_value(&mut self, val: f32) {
+        self.value = val.clamp(0.0_f32, 1.0_f32);
+        self.tracking = false;
+    }
+
+    /// `takeover.process(p)` is the active value given the physical pot position `p` in `[0.0, 1.0]`
+    pub fn process(&mut self, position: f32) -> f32 {
+        let anchor = *self.anchor.get_or_insert(position);
+        let delta = position - anchor;
+        let moved = NOISE_THRESHOLD < delta.max(-delta);
+        if moved {
+            self.anchor = Some(position);
+        }
+
+        if !self.tracking && moved {
+            let dist = position - self.value;
+            let crossed = dist.max(-dist) < NOISE_THRESHOLD
+                || (anchor < self.value) != (position < self.value);
+
+            match self.mode {
+                TakeoverMode::Jump => self.tracking = true,
+                TakeoverMode::Pickup => self.tracking = crossed,
+                TakeoverMode::ScaledCatch if crossed => self.tracking = true,
+                TakeoverMode::ScaledCatch => {
+                    // the value covers the rest of its range in the time the pot covers the rest of its range
+                    self.value += if 0.0_f32 < delta {
+                        delta * (1.0_f32 - self.value) / (1.0_f32 - anchor).max(f32::EPSILON)
+                    } else {
+                        delta * self.value / anchor.max(f32::EPSILON)
+                    };
+                    self.value = self.value.clamp(0.0_f32, 1.0_f32);
+                }
+            }
+        }
+
+        if self.tracking {
+            self.value = position;
+        }
+        self.value
+    }
+}
+
+/// Changes in pot position smaller than this are treated as noise rather than the pot being moved
+const NOISE_THRESHOLD: f32 = 0.01_f32;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `held_at(m, p, v)` is a pot takeover in mode `m` with the pot resting at `p` and the value then set to `v`
+    fn held_at(mode: TakeoverMode, position: f32, val: f32) -> PotTakeover {
+        let mut takeover = PotTakeover::new();
+        takeover.set_mode(mode);
+        takeover.process(position);
+        takeover.set_value(val);
+        takeover
+    }
+
+    /// `sweep(t, a, b)` moves the pot of the takeover `t` from `a` to `b` in small steps
+    ///
+    /// # Returns:
+    ///
+    /// * the value after each step
+    fn sweep(takeover: &mut PotTakeover, from: f32, to: f32) -> [f32; 20] {
+        let mut vals = [0.0_f32; 20];
+        for (i, val) in vals.iter_mut().enumerate() {
+            *val = takeover.process(from + (to - from) * (i + 1) as f32 / 20.0_f32);
+        }
+        vals
+    }
+
+    #[test]
+    fn the_pot_is_followed_until_a_value_is_set() {
+        let mut takeover = PotTakeover::new();
+        assert_eq!(takeover.process(0.3), 0.3);
+        assert_eq!(takeover.process(0.305), 0.305);
+    }
+
+    #[test]
+    fn jump_takes_over_once_the_pot_moves() {
+        let mut takeover = held_at(TakeoverMode::Jump, 0.2, 0.6);
+        // wobbles within the noise don't count as moving the pot
+        assert_eq!(takeover.process(0.205), 0.6);
+        assert_eq!(takeover.process(0.195), 0.6);
+        assert_eq!(takeover.process(0.22), 0.22);
+    }
+
+    #[test]
+    fn pickup_waits_for_the_pot_to_cross_from_below() {
+        let mut takeover = held_at(TakeoverMode::Pickup, 0.2, 0.6);
+        assert!(sweep(&mut takeover, 0.2, 0.5).iter().all(|&v| v == 0.6));
+        assert_eq!(sweep(&mut takeover, 0.5, 0.7)[19], 0.7);
+    }
+
+    #[test]
+    fn pickup_waits_for_the_pot_to_cross_from_above() {
+        let mut takeover = held_at(TakeoverMode::Pickup, 0.9, 0.4);
+        assert!(sweep(&mut takeover, 0.9, 0.5).iter().all(|&v| v == 0.4));
+        // moving back away from the value doesn't pick it up either
+        assert!(sweep(&mut takeover, 0.5, 0.8).iter().all(|&v| v == 0.4));
+        assert_eq!(sweep(&mut takeover, 0.8, 0.3)[19], 0.3);
+    }
+
+    #[test]
+    fn scaled_catch_meets_the_pot_at_the_end_stop() {
+        let mut takeover = held_at(TakeoverMode::ScaledCatch, 0.2, 0.6);
+        let vals = sweep(&mut takeover, 0.2, 1.0);
+        // the value moves with the pot, at half its speed here since it has half as far to go
+        assert!(vals.windows(2).all(|w| w[0] < w[1] + 1e-6));
+        assert!(0.6 < vals[0] && vals[0] < 0.7, "{}", vals[0]);
+        assert!((vals[19] - 1.0).abs() < 1e-4, "{}", vals[19]);
+        // once caught the pot is followed again
+        assert_eq!(takeover.process(0.8), 0.8);
+
+        let mut takeover = held_at(TakeoverMode::ScaledCatch, 0.8, 0.3);
+        let vals = sweep(&mut takeover, 0.8, 0.0);
+        assert!(vals.windows(2).all(|w| w[0] + 1e-6 > w[1]));
+        assert!(vals[19].abs() < 1e-4, "{}", vals[19]);
+    }
+
+    #[test]
+    fn set_values_are_held_until_released() {
+        let mut takeover = held_at(TakeoverMode::Pickup, 0.5, 0.8);
+        sweep(&mut takeover, 0.5, 0.9);
+        assert_eq!(takeover.process(0.9), 0.9);
+
+        // a new value is held again, and can be replaced while held
+        takeover.set_value(0.3);
+        assert_eq!(takeover.process(0.9), 0.3);
+        takeover.set_value(1.5);
+        assert_eq!(takeover.process(0.9), 1.0);
+
+        // the pot is already at the far side of the new value, so it only picks up once it reaches the end stop
+        assert!(sweep(&mut takeover, 0.9, 0.5).iter().all(|&v| v == 1.0));
+        assert_eq!(sweep(&mut takeover, 0.5, 1.0)[19], 1.0);
+        assert_eq!(takeover.process(0.6), 0.6);
+    }
+}
@@ -0,0 +1,20 @@
+/// `ms_to_ticks(t, r)` is the time `t` in milliseconds as a whole number of ticks at `r` hertz
+///
+/// The time is rounded up so that windows and pulses are never shorter than asked for, and it is never less than one
+/// tick.
+pub fn ms_to_ticks(time_ms: u32, tick_rate_hz: u32) -> u32 {
+    (time_ms * tick_rate_hz).div_ceil(1_000).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ms_to_ticks_rounds_up() {
+        assert_eq!(ms_to_ticks(10, 1_000), 10);
+        assert_eq!(ms_to_ticks(5, 300), 2);
+        assert_eq!(ms_to_ticks(50, 30), 2);
+        assert_eq!(ms_to_ticks(0, 300), 1);
+    }
+}
@@ -1,24 +1,97 @@
-use crate::board::{AdcPin, Board, Switch3wayState};
+use crate::{
+    arpeggiator::MAX_OCTAVES,
+    board::{self, AdcPin, Board, Switch3wayState, DAC8164_MAX_VOLTS},
+    gesture::{Gesture, GestureRecognizer},
+    settings,
+    smoothing::{PotSmoother, PotSmoothing},
+    takeover::{PotTakeover, TakeoverMode},
+};
 
 /// The user interface is represented here (i.e. the front panel pots and switches that the user interacts with)
 pub struct UiState {
     pitch_mode: PitchMode,
+    bender_enabled: bool,
+    vco_pot_mode: VcoPotMode,
+    vco_span_notes: u8,
 
     vco_lev: f32,
     modosc_lev: f32,
     vcf_lev: f32,
     delay_lev: f32,
+
+    bipolar_pots: [bool; NUM_LEVEL_POTS],
+    bipolar_center_volts: f32,
+    pot_responses: [PotResponse; NUM_LEVEL_POTS],
+    pot_smoothers: [PotSmoother; NUM_LEVEL_POTS],
+    pot_takeovers: [PotTakeover; NUM_LEVEL_POTS],
+    pot_positions: [f32; NUM_LEVEL_POTS],
+
+    gestures: Option<GestureRecognizer>,
+    menu: SettingsMenu,
+}
+
+/// A settings menu played on the ribbon is represented here, for changing settings without a MIDI controller
+///
+/// The ribbon is divided into slots, one for each item in `MENU_ITEMS`. Touching a slot selects its item and plays a
+/// note for the slot on the VCO output, the slots go up the major scale from the bottom of the ribbon. Turning the VCO
+/// pot after selecting an item steps through its options, and each new option is sent to the settings as if it were a
+/// MIDI control change.
+struct SettingsMenu {
+    active: bool,
+    item: usize,
+    option: Option<u8>,
+    note: u8,
+    pot_anchor: Option<f32>,
+    change: Option<MenuChange>,
+}
+
+/// An option picked in the settings menu is represented here
+///
+/// * `control` - the MIDI control number of the setting
+///
+/// * `value` - the control change value for the option
+///
+/// * `option` - the index of the option, counting from zero
+#[derive(Clone, Copy)]
+pub struct MenuChange {
+    pub control: u8,
+    pub value: u8,
+    pub option: u8,
 }
 
-/// There are three modes for the ribbon pitch information
+/// A setting which can be changed from the menu is represented here, as its MIDI control and number of options
+struct MenuItem {
+    control: u8,
+    num_options: u8,
+}
+
+/// There are four modes for the ribbon pitch information
+///
+/// `Bend` treats the ribbon as a relative pitch-bend strip, the first touch sets the zero point and sliding bends the
+/// pitch up or down from there. It takes the place of `Smooth` on the mode switch when the bender is enabled.
 #[derive(Clone, Copy)]
 pub enum PitchMode {
     HardQuantize,
     Assist,
     Smooth,
+    Bend,
+}
+
+/// The VCO level pot can either attenuate the ribbon or select how many notes the ribbon spans
+///
+/// In `Attenuate` mode the ribbon voltage is simply scaled, so at partial pot settings the frets no longer line up with
+/// the note boundaries. In `NoteSpan` mode the pot selects a whole number of notes, and the ribbon is always divided
+/// into that many evenly spaced, in-tune frets.
+#[derive(Clone, Copy)]
+pub enum VcoPotMode {
+    Attenuate,
+    NoteSpan,
 }
 
 /// Each main ribbon signal has its own attenuator
+///
+/// The MODOSC, VCF, and Delay pots can also be bipolar attenuverters, with zero at the center detent and inverted
+/// signals when turned CCW. The VCO pot is always unipolar so the VCO keeps tracking.
 #[derive(Clone, Copy)]
 pub enum LevelPot {
     Vco,
@@ -27,15 +100,75 @@ pub enum LevelPot {
     Delay,
 }
 
+/// The response curves which can be applied to the level pots are represented here
+///
+/// * `Linear` - the pot position as-is
+///
+/// * `AudioLog` - an audio taper, the center of the pot is 10% of the way up for fine control near zero
+///
+/// * `Exponential` - a steeper curve than the audio taper, the center of the pot is about 3% of the way up
+///
+/// * `SCurve` - fine control near both ends of the pot and a faster change through the middle
+#[derive(Clone, Copy)]
+pub enum PotCurve {
+    Linear,
+    AudioLog,
+    Exponential,
+    SCurve,
+}
+
+/// The response of a level pot is represented here, a curve followed by end stops
+///
+/// The end stops `min` and `max` are the values at the fully CCW and fully CW ends of the curve, both in `[0.0, 1.0]`.
+/// For bipolar pots the curve and end stops apply to each half of the pot, working outwards from the center.
+#[derive(Clone, Copy)]
+pub struct PotResponse {
+    pub curve: PotCurve,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl PotResponse {
+    /// The response which leaves the pot position unchanged
+    pub const LINEAR: PotResponse = PotResponse {
+        curve: PotCurve::Linear,
+        min: 0.0_f32,
+        max: 1.0_f32,
+    };
+}
+
 impl UiState {
     /// `UiState::new()` is a new UI state initialized to default values.
     pub fn new() -> Self {
         Self {
             pitch_mode: PitchMode::Smooth,
+            bender_enabled: false,
+            vco_pot_mode: VcoPotMode::Attenuate,
+            vco_span_notes: MAX_VCO_SPAN_NOTES,
             vco_lev: 0.0_f32,
             modosc_lev: 0.0_f32,
             vcf_lev: 0.0_f32,
             delay_lev: 0.0_f32,
+
+            bipolar_pots: [false; NUM_LEVEL_POTS],
+            bipolar_center_volts: DAC8164_MAX_VOLTS / 2.0_f32,
+            pot_responses: [PotResponse::LINEAR; NUM_LEVEL_POTS],
+            pot_smoothers: [
+                PotSmoother::new(board::TIM6_FREQ_HZ as f32, POT_SMOOTHING_CUTOFF_HZ),
+                PotSmoother::new(board::TIM6_FREQ_HZ as f32, POT_SMOOTHING_CUTOFF_HZ),
+                PotSmoother::new(board::TIM6_FREQ_HZ as f32, POT_SMOOTHING_CUTOFF_HZ),
+                PotSmoother::new(board::TIM6_FREQ_HZ as f32, POT_SMOOTHING_CUTOFF_HZ),
+            ],
+            pot_takeovers: [
+                PotTakeover::new(),
+                PotTakeover::new(),
+                PotTakeover::new(),
+                PotTakeover::new(),
+            ],
+            pot_positions: [0.0_f32; NUM_LEVEL_POTS],
+
+            gestures: None,
+            menu: SettingsMenu::new(),
         }
     }
 
@@ -43,19 +176,32 @@ impl UiState {
     ///
     /// It is required to periodically call this function to updat the state of the UI controls. Since these controls
     /// are manually adjusted by the user, they don't need to be updated very fast, just fast enough that they don't
-    /// feel sluggish to the user.
+    /// feel sluggish to the user. The pot smoothing filters assume this function is called at the TIM6 rate.
     pub fn update(&mut self, board: &mut Board) {
-        self.pitch_mode = match board.read_mode_switch() {
+        let switch = board.read_mode_switch();
+
+        // the first reading is the position the switch was in at power-up
+        self.gestures
+            .get_or_insert_with(|| GestureRecognizer::new(board::TIM6_FREQ_HZ, switch))
+            .poll(switch);
+
+        self.pitch_mode = match switch {
             Switch3wayState::Up => PitchMode::HardQuantize,
             Switch3wayState::Middle => PitchMode::Assist,
+            Switch3wayState::Down if self.bender_enabled => PitchMode::Bend,
             Switch3wayState::Down => PitchMode::Smooth,
         };
 
-        // the level pots are center-detent so we can easily dial in exactly midway
-        self.vco_lev = apply_midpoint_dead_zone(board.read_adc(AdcPin::PA3));
-        self.modosc_lev = apply_midpoint_dead_zone(board.read_adc(AdcPin::PA2));
-        self.vcf_lev = apply_midpoint_dead_zone(board.read_adc(AdcPin::PA1));
-        self.delay_lev = apply_midpoint_dead_zone(board.read_adc(AdcPin::PA0))
+        // the level pots are smoothed, may be held at a position set over MIDI until the pot takes over, are
+        // center-detent so we can easily dial in exactly midway, and then the response curve is applied
+        self.vco_lev = self.read_level_pot(board, AdcPin::PA3, LevelPot::Vco);
+        self.modosc_lev = self.read_level_pot(board, AdcPin::PA2, LevelPot::ModOsc);
+        self.vcf_lev = self.read_level_pot(board, AdcPin::PA1, LevelPot::Vcf);
+        self.delay_lev = self.read_level_pot(board, AdcPin::PA0, LevelPot::Delay);
+
+        self.vco_span_notes = level_to_note_span(self.vco_lev, self.vco_span_notes);
+
+        self.menu.update(self.pot_positions[LevelPot::Vco as usize]);
     }
 
     /// `ui.attenuate(v, c)` scales the input value `v` by the position of the front panel potentiometer `c`
@@ -69,13 +215,99 @@ impl UiState {
     /// # Returns:
     ///
     /// * `val` attenuated by the given control. If the panel control is turned CCW then turn `val` down, if it's
-    /// turned CW then turn `val` up.
+    /// turned CW then turn `val` up. If the control is bipolar then the center is zero and CCW inverts `val`.
     pub fn attenuate(&self, val: f32, control: LevelPot) -> f32 {
+        let lev = match control {
+            LevelPot::Vco => self.vco_lev,
+            LevelPot::ModOsc => self.modosc_lev,
+            LevelPot::Vcf => self.vcf_lev,
+            LevelPot::Delay => self.delay_lev,
+        };
+
+        if self.is_bipolar(control) {
+            val * (2.0_f32 * lev - 1.0_f32)
+        } else {
+            val * lev
+        }
+    }
+
+    /// `ui.center_offset(c)` is the voltage that the signals attenuated by control `c` are offset around
+    ///
+    /// Bipolar controls offset their signals to the bipolar center voltage so the DAC can swing both ways, unipolar
+    /// controls have no offset. The offset is added once to the final output of the channel.
+    pub fn center_offset(&self, control: LevelPot) -> f32 {
+        if self.is_bipolar(control) {
+            self.bipolar_center_volts
+        } else {
+            0.0_f32
+        }
+    }
+
+    /// `ui.set_bipolar(c, b)` sets whether control `c` is a bipolar attenuverter, the VCO control is never bipolar
+    pub fn set_bipolar(&mut self, control: LevelPot, bipolar: bool) {
+        self.bipolar_pots[control as usize] = bipolar;
+    }
+
+    /// `ui.set_bipolar_center_volts(v)` sets the voltage that bipolar controls are centered on, clamped to the DAC range
+    pub fn set_bipolar_center_volts(&mut self, volts: f32) {
+        self.bipolar_center_volts = volts.clamp(0.0_f32, DAC8164_MAX_VOLTS);
+    }
+
+    /// `ui.set_response(c, r)` sets the response of control `c` to `r`
+    pub fn set_response(&mut self, control: LevelPot, response: PotResponse) {
+        self.pot_responses[control as usize] = response;
+    }
+
+    /// `ui.set_pot_smoothing(s)` sets the kind of smoothing used for all of the level pots
+    pub fn set_pot_smoothing(&mut self, smoothing: PotSmoothing) {
+        for smoother in self.pot_smoothers.iter_mut() {
+            smoother.set_smoothing(smoothing);
+        }
+    }
+
+    /// `ui.set_takeover_mode(m)` sets how all of the level pots take control back after their positions are set
+    pub fn set_takeover_mode(&mut self, mode: TakeoverMode) {
+        for takeover in self.pot_takeovers.iter_mut() {
+            takeover.set_mode(mode);
+        }
+    }
+
+    /// `ui.set_pot_position(c, p)` sets control `c` to act as if it were at position `p` in `[0.0, 1.0]`
+    ///
+    /// The position is held until the physical pot takes over, as set by the takeover mode.
+    pub fn set_pot_position(&mut self, control: LevelPot, position: f32) {
+        self.pot_takeovers[control as usize].set_value(position);
+    }
+
+    /// `ui.read_level_pot(b, p, c)` is the value of control `c` read from pin `p` of board `b`, with the smoothing,
+    /// takeover, dead zone, and response applied
+    fn read_level_pot(&mut self, board: &mut Board, pin: AdcPin, control: LevelPot) -> f32 {
+        let smoothed = self.pot_smoothers[control as usize].process(board.read_adc(pin));
+        self.pot_positions[control as usize] = smoothed;
+        let position = self.pot_takeovers[control as usize].process(smoothed);
+        let val = apply_midpoint_dead_zone(position);
+        let response = &self.pot_responses[control as usize];
+
+        if self.is_bipolar(control) {
+            // each half of a bipolar pot gets the response, working outwards from the center
+            let bipolar = 2.0_f32 * val - 1.0_f32;
+            let magnitude = apply_response(bipolar.max(-bipolar), response);
+            let bipolar = if bipolar < 0.0_f32 {
+                -magnitude
+            } else {
+                magnitude
+            };
+            (bipolar + 1.0_f32) / 2.0_f32
+        } else {
+            apply_response(val, response)
+        }
+    }
+
+    /// `ui.is_bipolar(c)` is true iff control `c` is a bipolar attenuverter
+    pub fn is_bipolar(&self, control: LevelPot) -> bool {
         match control {
-            LevelPot::Vco => val * self.vco_lev,
-            LevelPot::ModOsc => val * self.modosc_lev,
-            LevelPot::Vcf => val * self.vcf_lev,
-            LevelPot::Delay => val * self.delay_lev,
+            LevelPot::Vco => false,
+            _ => self.bipolar_pots[control as usize],
         }
     }
 
@@ -83,6 +315,196 @@ impl UiState {
     pub fn pitch_mode(&self) -> PitchMode {
         self.pitch_mode
     }
+
+    /// `ui.take_gesture()` is the mode switch gesture recognized since the last call, if any, self clearing
+    pub fn take_gesture(&mut self) -> Option<Gesture> {
+        self.gestures.as_mut().and_then(|g| g.take_gesture())
+    }
+
+    /// `ui.set_menu_active(a)` opens the settings menu if `a` is true, otherwise closes it
+    pub fn set_menu_active(&mut self, active: bool) {
+        self.menu.set_active(active);
+    }
+
+    /// `ui.menu_active()` is true iff the settings menu is open, the ribbon then selects menu items instead of playing
+    pub fn menu_active(&self) -> bool {
+        self.menu.active
+    }
+
+    /// `ui.menu_select(r)` selects the menu item in the slot under the ribbon value `r` in `[0.0, 1.0]`
+    pub fn menu_select(&mut self, ribbon_val: f32) {
+        self.menu.select(ribbon_val);
+    }
+
+    /// `ui.take_menu_change()` is the latest option picked in the menu, if any, self clearing
+    pub fn take_menu_change(&mut self) -> Option<MenuChange> {
+        self.menu.change.take()
+    }
+
+    /// `ui.menu_note()` is the note of the selected menu slot in semitones above 0 volts, played while it is touched
+    pub fn menu_note(&self) -> u8 {
+        self.menu.note
+    }
+
+    /// `ui.set_bender_enabled(e)` sets whether the lower switch position selects the `Bend` mode instead of `Smooth`
+    pub fn set_bender_enabled(&mut self, enabled: bool) {
+        self.bender_enabled = enabled;
+    }
+
+    /// `ui.vco_pot_mode()` is the current enumerated behavior of the VCO level pot
+    pub fn vco_pot_mode(&self) -> VcoPotMode {
+        self.vco_pot_mode
+    }
+
+    /// `ui.set_vco_pot_mode(m)` sets the behavior of the VCO level pot to `m`
+    pub fn set_vco_pot_mode(&mut self, mode: VcoPotMode) {
+        self.vco_pot_mode = mode;
+    }
+
+    /// `ui.vco_span_notes()` is the number of notes the ribbon spans when the VCO pot is in `NoteSpan` mode
+    ///
+    /// The span is in `[1, MAX_VCO_SPAN_NOTES]`, fully CCW is a single note and fully CW is the whole ribbon range.
+    pub fn vco_span_notes(&self) -> u8 {
+        self.vco_span_notes
+    }
+}
+
+impl SettingsMenu {
+    /// `SettingsMenu::new()` is a new closed menu
+    fn new() -> Self {
+        Self {
+            active: false,
+            item: 0,
+            option: None,
+            note: MENU_BASE_NOTE,
+            pot_anchor: None,
+            change: None,
+        }
+    }
+
+    /// `menu.set_active(a)` opens the menu if `a` is true, otherwise closes it
+    fn set_active(&mut self, active: bool) {
+        self.active = active;
+        self.pot_anchor = None;
+        self.change = None;
+    }
+
+    /// `menu.select(r)` selects the item in the slot under the ribbon value `r`
+    fn select(&mut self, ribbon_val: f32) {
+        let item = ((ribbon_val * MENU_ITEMS.len() as f32) as usize).min(MENU_ITEMS.len() - 1);
+        if item != self.item {
+            // the pot has to move before it changes the newly selected item
+            self.item = item;
+            self.option = None;
+            self.pot_anchor = None;
+        }
+        self.note = MENU_BASE_NOTE + MAJOR_SCALE_SEMITONES[item];
+    }
+
+    /// `menu.update(p)` updates the menu with the VCO pot position `p`, at the TIM6 rate
+    fn update(&mut self, pot_position: f32) {
+        if !self.active {
+            return;
+        }
+
+        let anchor = *self.pot_anchor.get_or_insert(pot_position);
+        let moved = MENU_POT_THRESHOLD < (pot_position - anchor).max(anchor - pot_position);
+        if self.option.is_none() && !moved {
+            return;
+        }
+
+        let item = &MENU_ITEMS[self.item];
+        let option = ((pot_position * item.num_options as f32) as u8).min(item.num_options - 1);
+        if self.option != Some(option) {
+            self.option = Some(option);
+            self.change = Some(MenuChange {
+                control: item.control,
+                value: option_to_cc(option, item.num_options),
+                option,
+            });
+        }
+    }
+}
+
+/// The settings which can be changed from the menu, in order from the bottom of the ribbon
+const MENU_ITEMS: [MenuItem; 8] = [
+    MenuItem {
+        control: settings::CC_ROOT,
+        num_options: 12,
+    },
+    MenuItem {
+        control: settings::CC_SCALE,
+        num_options: 2,
+    },
+    MenuItem {
+        control: settings::CC_GATE_MODE,
+        num_options: 3,
+    },
+    MenuItem {
+        control: settings::CC_GATE_MERGE,
+        num_options: 5,
+    },
+    MenuItem {
+        control: settings::CC_ARP_ORDER,
+        num_options: 5,
+    },
+    MenuItem {
+        control: settings::CC_ARP_OCTAVES,
+        num_options: MAX_OCTAVES,
+    },
+    MenuItem {
+        control: settings::CC_POT_SMOOTHING,
+        num_options: 3,
+    },
+    MenuItem {
+        control: settings::CC_TAKEOVER_MODE,
+        num_options: 3,
+    },
+];
+
+/// The slot notes go up the major scale so each slot is easy to tell apart by ear
+const MAJOR_SCALE_SEMITONES: [u8; 8] = [0, 2, 4, 5, 7, 9, 11, 12];
+
+/// The lowest menu slot note, C3
+const MENU_BASE_NOTE: u8 = 36;
+
+/// The VCO pot must move this far after selecting an item before it changes the item
+const MENU_POT_THRESHOLD: f32 = 0.02_f32;
+
+/// `option_to_cc(o, n)` is the control change value in the middle of option `o` of `n` evenly sized options
+fn option_to_cc(option: u8, num_options: u8) -> u8 {
+    ((2 * option as u16 + 1) * 64 / num_options as u16) as u8
+}
+
+/// The number of level pots
+pub const NUM_LEVEL_POTS: usize = 4;
+
+/// The cutoff frequency of the pot smoothing filters, low enough to remove jitter but high enough to feel immediate
+const POT_SMOOTHING_CUTOFF_HZ: f32 = 4.0_f32;
+
+/// The maximum number of notes that the ribbon can span, 4 octaves inclusive of the top note
+pub const MAX_VCO_SPAN_NOTES: u8 = 49;
+
+/// `level_to_note_span(v, s)` is the level pot value `v` converted to a whole number of notes, with hysteresis
+///
+/// # Arguments:
+///
+/// * `val` - the level pot value, must be in `[0.0, 1.0]`
+///
+/// * `prev_span` - the previous note span, used to keep the span from jittering when the pot sits near a boundary
+fn level_to_note_span(val: f32, prev_span: u8) -> u8 {
+    // the pot must move a little past the boundary between two spans before the span changes
+    const HYSTERESIS: f32 = 0.2_f32;
+
+    let span = 1.0_f32 + val * (MAX_VCO_SPAN_NOTES - 1) as f32;
+    let dist_from_prev = span - prev_span as f32;
+
+    if dist_from_prev.max(-dist_from_prev) < 0.5_f32 + HYSTERESIS {
+        prev_span
+    } else {
+        // add one half and truncate to round to the nearest span
+        ((span + 0.5_f32) as u8).clamp(1, MAX_VCO_SPAN_NOTES)
+    }
 }
 
 /// `apply_midpoint_dead_zone(v)` is the value `v` with a small dead zone in the center of the range
@@ -110,3 +532,198 @@ fn apply_midpoint_dead_zone(val: f32) -> f32 {
         SLOPE * (val - DEAD_ZONE_END) + MIDPOINT
     }
 }
+
+/// `apply_response(v, r)` is the value `v` shaped by the response curve of `r` and scaled between its end stops
+///
+/// # Arguments:
+///
+/// * `val` - the value to shape, must be in `[0.0, 1.0]`
+///
+/// * `response` - the curve and end stops to apply
+fn apply_response(val: f32, response: &PotResponse) -> f32 {
+    // exponential curves with these constants cross the center of the pot at 10% and about 3%
+    const AUDIO_LOG_K: f32 = 4.394_449_f32; // 2 ln(9)
+    const EXPONENTIAL_K: f32 = 6.9_f32;
+
+    let shaped = match response.curve {
+        PotCurve::Linear => val,
+        PotCurve::AudioLog => exp_curve(val, AUDIO_LOG_K),
+        PotCurve::Exponential => exp_curve(val, EXPONENTIAL_K),
+        // smoothstep
+        PotCurve::SCurve => val * val * (3.0_f32 - 2.0_f32 * val),
+    };
+
+    response.min + (response.max - response.min) * shaped
+}
+
+/// `exp_curve(v, k)` is the value `v` in `[0.0, 1.0]` on an exponential curve of steepness `k`, from 0 to 1
+fn exp_curve(val: f32, k: f32) -> f32 {
+    (libm::expf(k * val) - 1.0_f32) / (libm::expf(k) - 1.0_f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVES: [PotCurve; 4] = [
+        PotCurve::Linear,
+        PotCurve::AudioLog,
+        PotCurve::Exponential,
+        PotCurve::SCurve,
+    ];
+
+    /// `response(c)` is the response with the curve `c` and the full range of end stops
+    fn response(curve: PotCurve) -> PotResponse {
+        PotResponse {
+            curve,
+            ..PotResponse::LINEAR
+        }
+    }
+
+    #[test]
+    fn curves_run_from_zero_to_one() {
+        for &curve in CURVES.iter() {
+            assert!(apply_response(0.0_f32, &response(curve)).abs() < 1.0e-6_f32);
+            assert!((apply_response(1.0_f32, &response(curve)) - 1.0_f32).abs() < 1.0e-6_f32);
+        }
+    }
+
+    #[test]
+    fn curves_are_monotonic() {
+        for &curve in CURVES.iter() {
+            let mut prev = apply_response(0.0_f32, &response(curve));
+            for i in 1..=1_000 {
+                let val = apply_response(i as f32 / 1_000.0_f32, &response(curve));
+                assert!(prev < val);
+                prev = val;
+            }
+        }
+    }
+
+    #[test]
+    fn curve_midpoints() {
+        let midpoints = [0.5_f32, 0.1_f32, 0.031_f32, 0.5_f32];
+        for (&curve, &midpoint) in CURVES.iter().zip(midpoints.iter()) {
+            assert!((apply_response(0.5_f32, &response(curve)) - midpoint).abs() < 1.0e-3_f32);
+        }
+    }
+
+    #[test]
+    fn end_stops_scale_the_curve() {
+        let response = PotResponse {
+            curve: PotCurve::AudioLog,
+            min: 0.2_f32,
+            max: 0.7_f32,
+        };
+        assert!((apply_response(0.0_f32, &response) - 0.2_f32).abs() < 1.0e-6_f32);
+        assert!((apply_response(0.5_f32, &response) - 0.25_f32).abs() < 1.0e-3_f32);
+        assert!((apply_response(1.0_f32, &response) - 0.7_f32).abs() < 1.0e-6_f32);
+    }
+
+    /// `open_menu(r, p)` is an open settings menu with the item under the ribbon value `r` selected and the VCO pot
+    /// resting at `p`
+    fn open_menu(ribbon_val: f32, pot_position: f32) -> SettingsMenu {
+        let mut menu = SettingsMenu::new();
+        menu.set_active(true);
+        menu.select(ribbon_val);
+        menu.update(pot_position);
+        menu
+    }
+
+    #[test]
+    fn menu_opens_and_closes() {
+        let mut ui = UiState::new();
+        assert!(!ui.menu_active());
+        ui.set_menu_active(true);
+        assert!(ui.menu_active());
+        ui.set_menu_active(false);
+        assert!(!ui.menu_active());
+    }
+
+    #[test]
+    fn closing_the_menu_drops_its_change() {
+        let mut menu = open_menu(0.0, 0.5);
+        menu.update(0.9);
+        assert!(menu.change.is_some());
+        menu.set_active(false);
+        assert!(menu.change.is_none());
+
+        // a closed menu ignores the pot
+        menu.update(0.1);
+        assert!(menu.change.is_none());
+    }
+
+    #[test]
+    fn ribbon_slots_select_items() {
+        let mut menu = SettingsMenu::new();
+        let slots = [
+            (0.0, 0, 36),
+            (0.13, 1, 38),
+            (0.3, 2, 40),
+            (0.6, 4, 43),
+            (0.99, 7, 48),
+            (1.0, 7, 48),
+        ];
+        for &(ribbon_val, item, note) in slots.iter() {
+            menu.select(ribbon_val);
+            assert_eq!(menu.item, item);
+            assert_eq!(menu.note, note);
+        }
+    }
+
+    #[test]
+    fn pot_changes_pick_options() {
+        // the third slot is the gate mode, with three options
+        let mut menu = open_menu(0.3, 0.5);
+        assert!(menu.change.is_none());
+
+        // the pot has to move past the threshold before an option is picked
+        menu.update(0.51);
+        assert!(menu.change.is_none());
+        menu.update(0.9);
+        let change = menu.change.take().unwrap();
+        assert_eq!(change.control, settings::CC_GATE_MODE);
+        assert_eq!((change.option, change.value), (2, 106));
+
+        // moving within the same option changes nothing, then each new option is sent once
+        menu.update(0.95);
+        assert!(menu.change.is_none());
+        menu.update(0.1);
+        let change = menu.change.take().unwrap();
+        assert_eq!((change.option, change.value), (0, 21));
+
+        // selecting another item waits for the pot to move again
+        menu.select(0.0);
+        menu.update(0.1);
+        assert!(menu.change.is_none());
+        menu.update(0.5);
+        let change = menu.change.take().unwrap();
+        assert_eq!(change.control, settings::CC_ROOT);
+        assert_eq!(change.option, 6);
+    }
+
+    #[test]
+    fn menu_values_pick_the_same_option_in_the_settings() {
+        for (i, item) in MENU_ITEMS.iter().enumerate() {
+            let mut menu = open_menu((i as f32 + 0.5) / MENU_ITEMS.len() as f32, 0.0);
+            for option in 0..item.num_options {
+                let position = (option as f32 + 0.5) / item.num_options as f32;
+                menu.update(position);
+                let change = menu.change.take().unwrap();
+                assert_eq!(change.control, item.control);
+                assert_eq!(change.option, option);
+                // the same mapping the settings use to read an option from a control change value
+                assert_eq!(
+                    (change.value as u16 * item.num_options as u16 / 128) as u8,
+                    option
+                );
+            }
+        }
+
+        let mut settings = settings::Settings::new();
+        settings.handle_cc(settings::CC_ROOT, option_to_cc(7, 12));
+        assert_eq!(settings.root(), 7);
+        settings.handle_cc(settings::CC_ARP_OCTAVES, option_to_cc(2, MAX_OCTAVES));
+        assert_eq!(settings.arp_num_octaves(), 3);
+    }
+}
@@ -0,0 +1,142 @@
+/// An estimator for how hard the finger lands on the ribbon is represented here
+///
+/// The ribbon has no pressure sensor, but the way the reading settles when a finger lands says something about how
+/// firmly it landed. A firm, fast touch jumps far from the untouched level to the finger position in about one sample
+/// and settles right away, a hesitant touch makes partial contact and creeps and wobbles on the way down.
+pub struct TouchVelocityEstimator {
+    history: [f32; HISTORY_LEN],
+    next: usize,
+}
+
+impl TouchVelocityEstimator {
+    /// `TouchVelocityEstimator::new()` is a new touch velocity estimator with an empty history
+    pub fn new() -> Self {
+        Self {
+            history: [UNTOUCHED_LEVEL; HISTORY_LEN],
+            next: 0,
+        }
+    }
+
+    /// `estimator.poll(v)` records the raw ribbon ADC reading `v`, which must be in `[0.0, 1.0]`
+    ///
+    /// It is required to call this function at the fast ribbon sample rate, the timing constants assume 1kHz.
+    pub fn poll(&mut self, raw_adc_val: f32) {
+        self.history[self.next] = raw_adc_val;
+        self.next = (self.next + 1) % HISTORY_LEN;
+    }
+
+    /// `estimator.estimate()` is the attack intensity of the most recent touch in `[0.0, 1.0]`
+    ///
+    /// This is meant to be called right when the ribbon controller reports that a finger was just pressed, while the
+    /// landing is still in the recent history.
+    pub fn estimate(&self) -> f32 {
+        // the history in order from oldest to newest
+        let sample = |i: usize| self.history[(self.next + i) % HISTORY_LEN];
+
+        // the landing starts right after the last untouched sample
+        let landing_start = match (0..HISTORY_LEN)
+            .rev()
+            .find(|&i| UNTOUCHED_LEVEL <= sample(i))
+        {
+            Some(i) if i < HISTORY_LEN - 1 => i,
+            // either the finger is not down, or it took so long to land that it was a very soft touch
+            _ => return 0.0_f32,
+        };
+
+        let landed_val = sample(HISTORY_LEN - 1);
+        let total_drop = sample(landing_start) - landed_val;
+        if total_drop <= 0.0_f32 {
+            return 0.0_f32;
+        }
+
+        // a firm touch makes most of the drop in a single sample
+        let first_step = sample(landing_start) - sample(landing_start + 1);
+        let steepness = (first_step / total_drop).clamp(0.0_f32, 1.0_f32);
+
+        // and that first sample jumps a long way, where partial contact only creeps down from the untouched level
+        let jump = (first_step / FULL_JUMP).clamp(0.0_f32, 1.0_f32);
+
+        // a firm touch also settles quickly
+        let settle_samples = ((landing_start + 1)..HISTORY_LEN)
+            .rev()
+            .find(|&i| {
+                let err = sample(i) - landed_val;
+                SETTLE_TOLERANCE < err.max(-err)
+            })
+            .map_or(0, |i| i - landing_start);
+        let quickness = 1.0_f32 - settle_samples.min(SETTLE_WINDOW) as f32 / SETTLE_WINDOW as f32;
+
+        (steepness + quickness + jump) / 3.0_f32
+    }
+}
+
+/// The number of recent raw samples to remember, long enough to hold a whole landing
+const HISTORY_LEN: usize = 32;
+
+/// The number of samples a landing may take to settle before it counts as the softest possible touch
+const SETTLE_WINDOW: usize = 16;
+
+/// The wiper is pulled up to the reference when untouched, so readings above this level mean no finger is down
+const UNTOUCHED_LEVEL: f32 = 0.95_f32;
+
+/// A first step this large counts as the firmest possible jump, the series resistor keeps every finger position at
+/// least this far below the untouched level
+const FULL_JUMP: f32 = 0.3_f32;
+
+/// Readings within this distance of the final landed value count as settled, roughly a sixth of a semitone
+const SETTLE_TOLERANCE: f32 = 0.002_f32;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `estimate(t)` is the velocity estimated right after polling the raw ADC trace `t`
+    fn estimate(trace: &[f32]) -> f32 {
+        let mut estimator = TouchVelocityEstimator::new();
+        for &raw_adc_val in [1.0_f32; HISTORY_LEN].iter().chain(trace.iter()) {
+            estimator.poll(raw_adc_val);
+        }
+        estimator.estimate()
+    }
+
+    /// A firm tap in the middle of the ribbon, the reading lands in one sample with a little ringing
+    const FIRM_TAP: [f32; 6] = [1.0, 0.312, 0.301, 0.300, 0.300, 0.300];
+
+    /// A soft touch in the same place, partial contact creeps down over several samples and wobbles before settling
+    const SOFT_TOUCH: [f32; 14] = [
+        1.0, 0.93, 0.81, 0.62, 0.47, 0.36, 0.33, 0.29, 0.31, 0.296, 0.304, 0.301, 0.300, 0.300,
+    ];
+
+    /// A firm tap near the top of the ribbon, where the reading has the least distance to fall
+    const FIRM_TAP_AT_TOP: [f32; 5] = [1.0, 0.61, 0.604, 0.604, 0.604];
+
+    /// A touch which catches the edge of the ribbon first, the first sample is partial contact still above the press
+    /// boundary at about 0.617, then the finger lands and settles at once
+    const GLANCING_TOUCH: [f32; 6] = [1.0, 0.80, 0.551, 0.550, 0.550, 0.550];
+
+    #[test]
+    fn firm_taps_are_loud() {
+        assert!(0.9 < estimate(&FIRM_TAP));
+        assert!(0.9 < estimate(&FIRM_TAP_AT_TOP));
+    }
+
+    #[test]
+    fn soft_touches_are_quiet() {
+        let soft = estimate(&SOFT_TOUCH);
+        assert!(soft < 0.3, "{}", soft);
+        assert!(soft < estimate(&FIRM_TAP));
+    }
+
+    #[test]
+    fn glancing_touches_are_in_between() {
+        // the glancing touch settles as quickly as the firm tap, but its first step is short and only part of the drop
+        let glancing = estimate(&GLANCING_TOUCH);
+        assert!(glancing < estimate(&FIRM_TAP) - 0.15, "{}", glancing);
+        assert!(estimate(&SOFT_TOUCH) + 0.15 < glancing, "{}", glancing);
+    }
+
+    #[test]
+    fn no_touch_is_silent() {
+        assert_eq!(estimate(&[1.0; 4]), 0.0);
+    }
+}